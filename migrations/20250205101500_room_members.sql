@@ -0,0 +1,18 @@
+-- 房间成员从 rooms.members JSON 拆成独立表
+CREATE TABLE room_members (
+    room_id int NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    role int NOT NULL DEFAULT 3,
+    joined_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (room_id, user_id),
+    INDEX idx_room_members_user (user_id)
+);
+
+-- 用旧的 members 数组回填
+INSERT IGNORE INTO room_members (room_id, user_id)
+SELECT r.id, m.user_id
+FROM rooms r,
+    JSON_TABLE(r.members, '$[*]' COLUMNS (user_id BIGINT UNSIGNED PATH '$')) m
+WHERE r.members IS NOT NULL AND JSON_VALID(r.members);
+
+ALTER TABLE rooms DROP COLUMN members;
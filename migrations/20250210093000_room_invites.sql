@@ -0,0 +1,12 @@
+-- 房间邀请：直接邀请某个用户（invitee），或者生成可分享的邀请码（code）
+CREATE TABLE room_invites (
+    id int PRIMARY KEY AUTO_INCREMENT,
+    room_id int NOT NULL,
+    inviter BIGINT UNSIGNED NOT NULL,
+    invitee BIGINT UNSIGNED,
+    code VARCHAR(64) UNIQUE,
+    status int NOT NULL DEFAULT 0,
+    expires_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_room_invites_invitee (invitee, status)
+);
@@ -0,0 +1,8 @@
+-- 一对一私聊房间，(user_low, user_high) 唯一保证两人之间只有一个私聊房间
+CREATE TABLE direct_rooms (
+    user_low BIGINT UNSIGNED NOT NULL,
+    user_high BIGINT UNSIGNED NOT NULL,
+    room_id int NOT NULL UNIQUE,
+    PRIMARY KEY (user_low, user_high),
+    INDEX idx_direct_rooms_high (user_high)
+);
@@ -0,0 +1,14 @@
+-- 消息编辑、撤回
+ALTER TABLE chat_msgs
+    ADD COLUMN edited_at VARCHAR(50),
+    ADD COLUMN deleted TINYINT(1) NOT NULL DEFAULT 0;
+
+-- 每次编辑或撤回前的内容
+CREATE TABLE chat_msg_edits (
+    id int PRIMARY KEY AUTO_INCREMENT,
+    msg_id int NOT NULL,
+    old_message TEXT,
+    editor BIGINT UNSIGNED NOT NULL,
+    edit_time VARCHAR(50),
+    INDEX idx_chat_msg_edits_msg (msg_id)
+);
@@ -0,0 +1,5 @@
+-- 回复和话题：reply_to 是引用的消息，thread_id 是话题的根消息
+ALTER TABLE chat_msgs
+    ADD COLUMN reply_to int,
+    ADD COLUMN thread_id int,
+    ADD INDEX idx_chat_msgs_thread (thread_id, id);
@@ -0,0 +1,8 @@
+-- 消息表情回应，同一用户对同一消息的同一表情只记一次
+CREATE TABLE message_reactions (
+    msg_id int NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    emoji VARCHAR(64) NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (msg_id, user_id, emoji)
+);
@@ -0,0 +1,13 @@
+-- 消息里的 @提及，每个被提及的用户一行；kind 见 MentionKind
+CREATE TABLE mentions (
+    id int PRIMARY KEY AUTO_INCREMENT,
+    msg_id int NOT NULL,
+    room_id int NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    sender BIGINT UNSIGNED NOT NULL,
+    kind int NOT NULL DEFAULT 1,
+    is_read TINYINT(1) NOT NULL DEFAULT 0,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY uk_mentions_msg_user (msg_id, user_id),
+    INDEX idx_mentions_user (user_id, is_read)
+);
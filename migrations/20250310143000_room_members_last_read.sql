@@ -0,0 +1,2 @@
+-- 成员在房间里已读到的最后一条消息，用于计算未读数
+ALTER TABLE room_members ADD COLUMN last_read_id int NOT NULL DEFAULT 0;
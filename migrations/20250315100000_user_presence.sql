@@ -0,0 +1,5 @@
+-- 在线状态：客户端设置的状态（见 PresenceStatus）、自定义状态文字和最后在线时间
+ALTER TABLE users
+    ADD COLUMN status int NOT NULL DEFAULT 1,
+    ADD COLUMN status_text VARCHAR(100),
+    ADD COLUMN last_seen_at DATETIME;
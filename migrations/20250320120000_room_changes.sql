@@ -0,0 +1,10 @@
+-- 房间内的变化记录，自增 id 作为离线同步的游标；kind 见 ChangeKind
+CREATE TABLE room_changes (
+    id BIGINT PRIMARY KEY AUTO_INCREMENT,
+    room_id int NOT NULL,
+    kind int NOT NULL,
+    msg_id int,
+    user_id BIGINT UNSIGNED,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_room_changes_room (room_id, id)
+);
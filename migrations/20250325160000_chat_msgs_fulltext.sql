@@ -0,0 +1,2 @@
+-- 消息全文检索，ngram 分词以支持中文
+ALTER TABLE chat_msgs ADD FULLTEXT INDEX ft_chat_msgs_message (message) WITH PARSER ngram;
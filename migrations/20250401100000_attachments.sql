@@ -0,0 +1,14 @@
+-- 上传的附件，发送消息时关联到消息和房间
+CREATE TABLE attachments (
+    id int PRIMARY KEY AUTO_INCREMENT,
+    uploader BIGINT UNSIGNED NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    size BIGINT NOT NULL,
+    mime VARCHAR(100) NOT NULL,
+    sha256 CHAR(64) NOT NULL,
+    storage_key VARCHAR(255) NOT NULL,
+    msg_id int,
+    room_id int,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    INDEX idx_attachments_msg (msg_id)
+);
@@ -0,0 +1,7 @@
+-- 图片附件的尺寸和缩略图，缩略图在后台生成，生成前为空
+ALTER TABLE attachments
+    ADD COLUMN width int,
+    ADD COLUMN height int,
+    ADD COLUMN thumb_key VARCHAR(255),
+    ADD COLUMN thumb_width int,
+    ADD COLUMN thumb_height int;
@@ -0,0 +1,6 @@
+-- 消息类型：1 纯文本，2 markdown，3 系统通知，4 卡片（message 里存卡片的 JSON）
+ALTER TABLE chat_msgs
+    ADD COLUMN msg_type TINYINT NOT NULL DEFAULT 1;
+
+-- 之前以系统身份发出的消息都是系统通知
+UPDATE chat_msgs SET msg_type = 3 WHERE sender = 0;
@@ -0,0 +1,32 @@
+-- 投票，每个投票对应房间里一条 msg_type = 5 的消息
+CREATE TABLE polls (
+    id int PRIMARY KEY AUTO_INCREMENT,
+    msg_id int NOT NULL,
+    room_id int NOT NULL,
+    creator BIGINT UNSIGNED NOT NULL,
+    question VARCHAR(500) NOT NULL,
+    multi_choice TINYINT(1) NOT NULL DEFAULT 0,
+    closes_at DATETIME,
+    closed TINYINT(1) NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE KEY uk_polls_msg (msg_id),
+    INDEX idx_polls_closes_at (closed, closes_at)
+);
+
+CREATE TABLE poll_options (
+    id int PRIMARY KEY AUTO_INCREMENT,
+    poll_id int NOT NULL,
+    position int NOT NULL,
+    text VARCHAR(200) NOT NULL,
+    INDEX idx_poll_options_poll (poll_id, position)
+);
+
+-- 单选投票每人只有一行，重新投票时先删掉旧的选择
+CREATE TABLE poll_votes (
+    poll_id int NOT NULL,
+    option_id int NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (poll_id, user_id, option_id),
+    INDEX idx_poll_votes_option (option_id)
+);
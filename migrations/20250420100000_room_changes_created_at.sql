@@ -0,0 +1,3 @@
+-- 同步时按写入时间找出还不稳定的变化
+ALTER TABLE room_changes
+    ADD INDEX idx_room_changes_created (created_at);
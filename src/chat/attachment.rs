@@ -0,0 +1,56 @@
+//! 消息附件
+//!
+//! 文件先通过 HTTP 上传得到附件 id，`SendMsg` 带上附件 id 后附件才关联到消息和房间，
+//! 之后只有房间成员能下载。
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{dao::attachment_dao::{get_attachments, get_msg_attachments}, models::attachment::Attachment, web::common::ErrorCode};
+
+use super::{chatserver::ChatState, protocol::AttachmentInfo};
+
+/// 一条消息最多带的附件数
+pub const MAX_MSG_ATTACHMENTS: usize = 10;
+
+pub fn attachment_info(attachment: &Attachment) -> AttachmentInfo {
+    AttachmentInfo {
+        id: attachment.id,
+        name: attachment.name.clone(),
+        size: attachment.size,
+        mime: attachment.mime.clone(),
+        url: format!("/attachments/{}", attachment.id),
+        width: attachment.width,
+        height: attachment.height,
+        thumbnail_url: attachment.thumb_key.as_ref().map(|_| format!("/attachments/{}/thumbnail", attachment.id)),
+        thumb_width: attachment.thumb_width,
+        thumb_height: attachment.thumb_height,
+    }
+}
+
+/// 多条消息的附件：msg_id -> 附件列表
+pub(crate) async fn msg_attachments(state: &ChatState, msg_ids: &[i32]) -> Result<HashMap<i32, Vec<AttachmentInfo>>> {
+    let mut map: HashMap<i32, Vec<AttachmentInfo>> = HashMap::new();
+    for attachment in get_msg_attachments(&state.pool, msg_ids).await? {
+        if let Some(msg_id) = attachment.msg_id {
+            map.entry(msg_id).or_default().push(attachment_info(&attachment));
+        }
+    }
+    Ok(map)
+}
+
+/// 发送前检查附件：必须是自己上传的，并且还没有发送过
+pub(crate) async fn check_attachments(state: &ChatState, ids: &[i32], uploader: u64) -> Result<Vec<Attachment>> {
+    if ids.len() > MAX_MSG_ATTACHMENTS {
+        return Err(ErrorCode::BadRequest.error(format!("at most {} attachments per message", MAX_MSG_ATTACHMENTS)));
+    }
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    let attachments = get_attachments(&state.pool, &ids).await?;
+    if attachments.len() != ids.len() || attachments.iter().any(|a| a.uploader != uploader || a.msg_id.is_some()) {
+        return Err(ErrorCode::AttachmentNotFound.error("attachment not found or already sent"));
+    }
+    Ok(attachments)
+}
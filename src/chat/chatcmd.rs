@@ -1,23 +1,18 @@
 use std::{collections::HashMap, sync::Arc};
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{Ok, Result};
 
 use log::{error, info};
-use serde::{Deserialize, Serialize};
 
-use crate::{dao::{chatmsg_dao::{create_chat_msg, get_chat_msg, get_chat_msg_limit}, room_dao::{self, get_room, get_rooms_by_member, get_rooms_by_type, update_room_members}, user_dao::get_user_in_id}, models::{chatmsg::ChatMessage, room::Room, user::User}};
+use crate::{dao::{chatmsg_dao::{count_thread_replies, count_unread, create_chat_msg, create_msg_with_attachments, delete_chat_msg, edit_chat_msg, get_chat_msg_by_id, get_last_msgs, get_msgs_after, get_msgs_before, get_thread_msgs}, mention_dao::mark_mentions_read_until, reaction_dao::{add_reaction, count_reaction, get_reactions, remove_reaction}, room_dao::{self, add_room_member, create_direct_room, get_direct_peers, get_direct_room, get_room, get_room_member, get_room_member_ids, get_room_members, get_rooms_by_member, get_rooms_by_type, remove_room_member, update_last_read, update_member_role, update_room_name}, user_dao::{get_user, get_user_in_id}}, models::{chatmsg::{ChatMessage, MsgType, SYSTEM_SENDER}, room::Room, room_member::RoomRole, user::User}, web::common::ErrorCode};
 
-use super::chatserver::ChatState;
+use super::{attachment, chatserver::{push_conn, ChatState, ConnSender}, content, invite, mention, poll, permission::{self, check_room, Permission}, presence, search, sync, typing, protocol::{ClientChatMsg, ClientCommand, ClientFrame, FrameKind, ReqCreateRoom, ReqEnter, ReqDeleteMsg, ReqMarkRead, ReqDeleteRoom, ReqEditMsg, ReqOpenDirect, ReqReaction, ReqLeaveRoom, ReqRemoveMember, ReqRenameRoom, ReqRoomMsgs, ReqSendMsg, ReqSetMemberRole, ReqThreadMsgs, ReactionCount, RoomInfo, RoomItem, RspError, RspMarkRead, RspMsgDeleted, RspReadReceipt, RspReactionChanged, RspRoomMsgs, RspThreadMsgs, SentMsg, ServerEvent}};
 
 
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ChatCammand {
-    pub cmd: String,
-    pub data: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Rooms;
+const DEFAULT_PAGE_SIZE: usize = 20;
+/// 客户端能指定的最大每页条数
+const MAX_PAGE_SIZE: usize = 100;
+/// 成员数不超过这个值的房间才推送已读回执
+const READ_RECEIPT_MAX_MEMBERS: usize = 20;
 
 #[repr(i32)]
 pub enum RoomType {
@@ -26,153 +21,617 @@ pub enum RoomType {
     Public = 3,
 }
 
-pub async fn hand_msg(state: Arc<ChatState>, msg: ChatCammand, user: &User) {
-    info!("hand msg:{:?}", msg);
-    let r = match msg.cmd.as_str() {
-        "Rooms" => rooms(state, msg, user).await,
-        "CreateRoom" => create_room(state, msg, user).await,
-        "Enter" => enter(state, msg, user).await,
-        "RoomMsgs" => room_msgs(state, msg, user).await,
-        "SendMsg" => send_msg(state, msg, user).await,
-        _ => Err(anyhow!(format!("unknown cmd:{:?}", msg))),
+/// 一条命令的处理上下文
+pub struct CmdCtx<'a> {
+    pub user: &'a User,
+    /// 发出请求的连接
+    pub conn_id: u64,
+    pub req_id: Option<String>,
+}
+
+impl CmdCtx<'_> {
+    /// 回复当前请求，只发给发出请求的那个连接
+    pub async fn reply(&self, state: &ChatState, event: &ServerEvent) -> Result<()> {
+        let data = event.encode(FrameKind::Reply, self.req_id.as_deref())?;
+        let sender = state.conn_map.read().await
+            .get(&self.user.id)
+            .and_then(|conns| conns.get(&self.conn_id))
+            .cloned();
+        if let Some(sender) = sender {
+            deliver(state, self.user.id, vec![(self.conn_id, sender)], data).await;
+        }
+        Ok(())
+    }
+
+    /// 回复当前请求，同时推送给房间里的其他在线成员以及自己的其他设备
+    pub async fn reply_and_broadcast(&self, state: &ChatState, room_id: i32, event: &ServerEvent) -> Result<()> {
+        self.reply(state, event).await?;
+        self.push_other_conns(state, event).await?;
+        for member in get_room_member_ids(&state.pool, room_id).await? {
+            if member != self.user.id {
+                send_to(state, member, event).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 同步给当前用户的其他设备
+    pub async fn push_other_conns(&self, state: &ChatState, event: &ServerEvent) -> Result<()> {
+        let data = event.encode(FrameKind::Push, None)?;
+        let conns = conns_of(state, self.user.id, Some(self.conn_id)).await;
+        deliver(state, self.user.id, conns, data).await;
+        Ok(())
+    }
+}
+
+async fn conns_of(state: &ChatState, user_id: u64, skip_conn: Option<u64>) -> Vec<(u64, ConnSender)> {
+    state.conn_map.read().await
+        .get(&user_id)
+        .map(|conns| conns.iter()
+            .filter(|(conn_id, _)| Some(**conn_id) != skip_conn)
+            .map(|(conn_id, sender)| (*conn_id, sender.clone()))
+            .collect())
+        .unwrap_or_default()
+}
+
+/// 逐个放进各连接的发送队列，见 [`push_conn`]
+async fn deliver(state: &ChatState, user_id: u64, conns: Vec<(u64, ConnSender)>, data: String) {
+    for (conn_id, sender) in conns {
+        push_conn(state, user_id, conn_id, &sender, data.clone()).await;
+    }
+}
+
+pub async fn hand_msg(state: Arc<ChatState>, frame: ClientFrame, user: &User, conn_id: u64) {
+    info!("hand msg:{:?}", frame);
+    let ctx = CmdCtx { user, conn_id, req_id: frame.req_id };
+    let name = frame.cmd.name();
+    if let Err(e) = permission::check(&state, user.id, &frame.cmd).await {
+        info!("permission denied:{}, {}", user.username, e);
+        send_error(&state, &ctx, &e, Some(name.to_string())).await;
+        return;
+    }
+    let r = match frame.cmd {
+        ClientCommand::Rooms => rooms(state.clone(), &ctx).await,
+        ClientCommand::CreateRoom(req) => create_room(state.clone(), req, &ctx).await,
+        ClientCommand::Enter(req) => enter(state.clone(), req, &ctx).await,
+        ClientCommand::RoomMsgs(req) => room_msgs(state.clone(), req, &ctx).await,
+        ClientCommand::SendMsg(req) => send_msg(state.clone(), req, &ctx).await,
+        ClientCommand::LeaveRoom(req) => leave_room(state.clone(), req, &ctx).await,
+        ClientCommand::RemoveMember(req) => remove_member(state.clone(), req, &ctx).await,
+        ClientCommand::RenameRoom(req) => rename_room(state.clone(), req, &ctx).await,
+        ClientCommand::SetMemberRole(req) => set_member_role(state.clone(), req, &ctx).await,
+        ClientCommand::DeleteRoom(req) => delete_room(state.clone(), req, &ctx).await,
+        ClientCommand::InviteUser(req) => invite::invite_user(state.clone(), req, &ctx).await,
+        ClientCommand::CreateInviteCode(req) => invite::create_invite_code(state.clone(), req, &ctx).await,
+        ClientCommand::AcceptInvite(req) => invite::accept_invite(state.clone(), req, &ctx).await,
+        ClientCommand::DeclineInvite(req) => invite::decline_invite(state.clone(), req, &ctx).await,
+        ClientCommand::Invites => invite::invites(state.clone(), &ctx).await,
+        ClientCommand::OpenDirect(req) => open_direct(state.clone(), req, &ctx).await,
+        ClientCommand::EditMsg(req) => edit_msg(state.clone(), req, &ctx).await,
+        ClientCommand::DeleteMsg(req) => delete_msg(state.clone(), req, &ctx).await,
+        ClientCommand::ThreadMsgs(req) => thread_msgs(state.clone(), req, &ctx).await,
+        ClientCommand::AddReaction(req) => change_reaction(state.clone(), req, true, &ctx).await,
+        ClientCommand::RemoveReaction(req) => change_reaction(state.clone(), req, false, &ctx).await,
+        ClientCommand::MyMentions(req) => mention::my_mentions(state.clone(), req, &ctx).await,
+        ClientCommand::ReadMentions(req) => mention::read_mentions(state.clone(), req, &ctx).await,
+        ClientCommand::MarkRead(req) => mark_read(state.clone(), req, &ctx).await,
+        ClientCommand::Typing(req) => typing::typing(state.clone(), req, &ctx).await,
+        ClientCommand::StopTyping(req) => typing::stop_typing(state.clone(), req, &ctx).await,
+        ClientCommand::SetStatus(req) => presence::set_status(state.clone(), req, &ctx).await,
+        ClientCommand::Presence(req) => presence::presence(state.clone(), req, &ctx).await,
+        ClientCommand::Ping => ctx.reply(&state, &ServerEvent::Pong).await,
+        // 收到任何帧都会刷新连接的活跃时间，这里不用处理
+        ClientCommand::Pong => Ok(()),
+        ClientCommand::Sync(req) => sync::sync(state.clone(), req, &ctx).await,
+        ClientCommand::SearchMsgs(req) => search::search(state.clone(), req, &ctx).await,
+        ClientCommand::CreatePoll(req) => poll::create_poll(state.clone(), req, &ctx).await,
+        ClientCommand::Vote(req) => poll::vote(state.clone(), req, &ctx).await,
+        ClientCommand::ClosePoll(req) => poll::close(state.clone(), req, &ctx).await,
     };
     if let Err(e) = r {
         error!("hand msg error:{}", e);
+        send_error(&state, &ctx, &e, Some(name.to_string())).await;
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RoomInfo {
-    rooms: Vec<Room>,
+/// 把处理失败的原因回给客户端
+pub async fn send_error(state: &ChatState, ctx: &CmdCtx<'_>, e: &anyhow::Error, cmd: Option<String>) {
+    let rsp = ServerEvent::Error(RspError::new(e, cmd));
+    if let Err(e) = ctx.reply(state, &rsp).await {
+        error!("send error rsp error:{}", e);
+    }
 }
 
-async fn rooms(state: Arc<ChatState>, _msg: ChatCammand, user: &User) -> Result<()> {
-    push_rooms(state, user).await
+/// 给某个用户的所有连接推送一条消息，用户不在线时忽略
+pub async fn send_to(state: &ChatState, user_id: u64, event: &ServerEvent) -> Result<()> {
+    let data = event.encode(FrameKind::Push, None)?;
+    let conns = conns_of(state, user_id, None).await;
+    deliver(state, user_id, conns, data).await;
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ReqCreateRoom {
-    room_type: i32,
-    room_name: String,
-    members: Vec<u64>
+async fn rooms(state: Arc<ChatState>, ctx: &CmdCtx<'_>) -> Result<()> {
+    ctx.reply(&state, &rooms_event(&state, ctx.user.id).await?).await
 }
 
-async fn create_room(state: Arc<ChatState>, msg: ChatCammand, user: &User) -> Result<()> {
-    let mut req: ReqCreateRoom = serde_json::from_str(&msg.data)?;
+async fn create_room(state: Arc<ChatState>, mut req: ReqCreateRoom, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    // 私聊房间统一走 OpenDirect，保证两人之间只有一个
+    if req.room_type == RoomType::Private as i32 {
+        req.members.retain(|member| *member != user.id);
+        if req.members.len() != 1 {
+            return Err(ErrorCode::BadRequest.error("private room must have exactly one other member"));
+        }
+        return open_direct(state, ReqOpenDirect { user_id: req.members[0] }, ctx).await;
+    }
     if !req.members.contains(&user.id) {
         req.members.push(user.id);
     }
-    let _ = room_dao::create_room(&state.pool, req.room_type, &req.room_name, &req.members).await?;
-    push_rooms(state, user).await
+    let _ = room_dao::create_room(&state.pool, req.room_type, &req.room_name, user.id, &req.members).await?;
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ReqEnter {
-    room_id: i32
-}
-
-async fn enter(state: Arc<ChatState>, msg: ChatCammand, user: &User) -> Result<()> {
-    let req: ReqEnter = serde_json::from_str(&msg.data)?;
-    let room = get_room(&state.pool, req.room_id).await.ok_or_else(|| anyhow!("room not found"))?;
-    let mut members: Vec<u64> = serde_json::from_str(&room.members)?;
-    if !members.contains(&user.id) {
-        members.push(user.id);
-        update_room_members(&state.pool, room.id, members).await?;
+async fn enter(state: Arc<ChatState>, req: ReqEnter, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let room = get_room(&state.pool, req.room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    // 非公共房间只能通过邀请加入
+    let newly_joined = get_room_member(&state.pool, room.id, user.id).await.is_none();
+    if room.room_type != RoomType::Public as i32 && newly_joined {
+        return Err(ErrorCode::Forbidden.error("room is not public, accept an invite to join"));
+    }
+    add_room_member(&state.pool, room.id, user.id, RoomRole::Member).await?;
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await?;
+    if newly_joined {
+        post_system_msg(&state, &room, &format!("{} 加入了房间", user.username)).await?;
     }
-    push_rooms(state, user).await?;
     Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ReqRoomMsgs {
-    room_id: i32,
-    last_id: Option<i32>
+async fn room_msgs(state: Arc<ChatState>, req: ReqRoomMsgs, ctx: &CmdCtx<'_>) -> Result<()> {
+    let limit = req.limit.map_or(DEFAULT_PAGE_SIZE, |limit| limit as usize).clamp(1, MAX_PAGE_SIZE);
+    let before = req.before.or(req.last_id);
+    let (msgs, has_more, has_more_after) = match (before, req.after, req.around) {
+        (before, None, None) => {
+            let (msgs, has_more) = page_before(&state, req.room_id, before, limit).await?;
+            (msgs, has_more, None)
+        }
+        (None, Some(after), None) => {
+            let (msgs, has_more) = page_after(&state, req.room_id, after, limit).await?;
+            (msgs, has_more, None)
+        }
+        (None, None, Some(around)) => {
+            let anchor = get_chat_msg_by_id(&state.pool, around).await
+                .ok()
+                .filter(|msg| msg.room_id == req.room_id)
+                .ok_or_else(|| ErrorCode::MsgNotFound.error("message not found"))?;
+            // 中心消息算在前半页里
+            let (mut msgs, has_more) = page_before(&state, req.room_id, Some(anchor.id + 1), limit.div_ceil(2)).await?;
+            let (after, has_more_after) = page_after(&state, req.room_id, anchor.id, limit / 2).await?;
+            msgs.extend(after);
+            (msgs, has_more, Some(has_more_after))
+        }
+        _ => return Err(ErrorCode::BadRequest.error("only one of before, after and around is allowed")),
+    };
+    let msgs = client_msgs(&state, ctx.user.id, msgs).await?;
+    let rsp = ServerEvent::RspRoomMsgs(RspRoomMsgs { room_id: req.room_id, msgs, has_more, has_more_after });
+    ctx.reply(&state, &rsp).await
+}
+
+/// id 小于 before 的一页消息，按 id 升序返回，多查一条判断是否还有更早的
+async fn page_before(state: &ChatState, room_id: i32, before: Option<i32>, limit: usize) -> Result<(Vec<ChatMessage>, bool)> {
+    let msgs = get_msgs_before(&state.pool, room_id, before, limit as i64 + 1).await?;
+    Ok(ascending_page(msgs, limit))
 }
-#[derive(Debug, Serialize, Deserialize)]
-struct RspRoomMsgs {
-    room_id: i32,
-    msgs: Vec<ClientChatMsg>,
+
+/// 话题里 id 小于 before 的一页回复，和 [`page_before`] 一样按 id 升序返回
+async fn thread_page_before(state: &ChatState, thread_id: i32, before: Option<i32>, limit: usize) -> Result<(Vec<ChatMessage>, bool)> {
+    let msgs = get_thread_msgs(&state.pool, thread_id, before, limit as i64 + 1).await?;
+    Ok(ascending_page(msgs, limit))
 }
-#[derive(Debug, Serialize, Deserialize)]
-struct ClientChatMsg {
-    msg: ChatMessage,
-    user_name: String
+
+/// 按 id 倒序多查了一条的结果，截成一页并转成升序
+fn ascending_page(mut msgs: Vec<ChatMessage>, limit: usize) -> (Vec<ChatMessage>, bool) {
+    let has_more = msgs.len() > limit;
+    msgs.truncate(limit);
+    msgs.reverse();
+    (msgs, has_more)
 }
 
-async fn room_msgs(state: Arc<ChatState>, msg: ChatCammand, user: &User) -> Result<()> {
-    let req: ReqRoomMsgs = serde_json::from_str(&msg.data)?;
-    let msgs = match req.last_id {
-        Some(last_id) => {
-            get_chat_msg_limit(&state.pool, req.room_id, last_id).await?
-        },
-        None => {
-            get_chat_msg(&state.pool, req.room_id).await?
-        },
-    };
+/// id 大于 after 的一页消息，按 id 升序返回
+async fn page_after(state: &ChatState, room_id: i32, after: i32, limit: usize) -> Result<(Vec<ChatMessage>, bool)> {
+    let mut msgs = get_msgs_after(&state.pool, room_id, after, limit as i64 + 1).await?;
+    let has_more = msgs.len() > limit;
+    msgs.truncate(limit);
+    Ok((msgs, has_more))
+}
+
+/// 补充发送者用户名、话题回复数、表情回应、附件和投票，viewer 为查看消息的用户
+pub(crate) async fn client_msgs(state: &ChatState, viewer: u64, msgs: Vec<ChatMessage>) -> Result<Vec<ClientChatMsg>> {
     let ids: Vec<u64> = msgs.iter().map(|msg| msg.sender).collect();
     let users: HashMap<u64, User> = get_user_in_id(&state.pool, &ids).await?.into_iter()
     .map(|user| (user.id, user)) // 使用user.id做key
     .collect();
+    let msg_ids: Vec<i32> = msgs.iter().map(|msg| msg.id).collect();
+    let reply_counts: HashMap<i32, i64> = count_thread_replies(&state.pool, &msg_ids).await?.into_iter().collect();
+    // 撤回的消息不再带回应、附件和投票
+    let live_ids: Vec<i32> = msgs.iter().filter(|msg| !msg.deleted).map(|msg| msg.id).collect();
+    let mut reactions: HashMap<i32, Vec<ReactionCount>> = HashMap::new();
+    for (msg_id, emoji, count, me) in get_reactions(&state.pool, &live_ids, viewer).await? {
+        reactions.entry(msg_id).or_default().push(ReactionCount { emoji, count, me });
+    }
+    let mut attachments = attachment::msg_attachments(state, &live_ids).await?;
+    let mut polls = poll::msg_polls(state, viewer, &live_ids).await?;
     let mut chat_msg_list = vec![];
-    for chatmsg in &msgs {
-        let mybe_user = users.get(&chatmsg.sender);
-        if let Some(user) = mybe_user {
-            chat_msg_list.push(ClientChatMsg {
-                msg: chatmsg.clone(),
-                user_name: user.username.clone(),
-            });
+    for chatmsg in msgs {
+        let user_name = if chatmsg.sender == SYSTEM_SENDER {
+            "system".to_string()
+        } else if let Some(user) = users.get(&chatmsg.sender) {
+            user.username.clone()
         } else {
-            chat_msg_list.push(ClientChatMsg {
-                msg: chatmsg.clone(),
-                user_name: "none".to_string(),
-            });
-        }
+            "none".to_string()
+        };
+        let reply_count = reply_counts.get(&chatmsg.id).copied().unwrap_or(0);
+        let reactions = reactions.remove(&chatmsg.id).unwrap_or_default();
+        let attachments = attachments.remove(&chatmsg.id).unwrap_or_default();
+        let poll = polls.remove(&chatmsg.id);
+        chat_msg_list.push(ClientChatMsg {
+            msg: chatmsg,
+            user_name,
+            reply_count,
+            reactions,
+            attachments,
+            poll,
+        });
+    }
+    Ok(chat_msg_list)
+}
+
+async fn change_reaction(state: Arc<ChatState>, req: ReqReaction, add: bool, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let emoji = req.emoji.trim();
+    if emoji.is_empty() || emoji.len() > 64 || emoji.chars().any(char::is_whitespace) {
+        return Err(ErrorCode::BadRequest.error("invalid emoji"));
     }
-    let rsp = ChatCammand {
-        cmd: "RspRoomMsgs".to_string(),
-        data: serde_json::to_string(&RspRoomMsgs { room_id: req.room_id, msgs: chat_msg_list })?,
+    let msg = get_chat_msg_by_id(&state.pool, req.msg_id).await
+        .map_err(|_| ErrorCode::MsgNotFound.error("message not found"))?;
+    if msg.deleted {
+        return Err(ErrorCode::MsgNotFound.error("message has been deleted"));
+    }
+    check_room(&state, msg.room_id, user.id, Permission::SendMsg).await?;
+    let changed = if add {
+        add_reaction(&state.pool, msg.id, user.id, emoji).await?
+    } else {
+        remove_reaction(&state.pool, msg.id, user.id, emoji).await?
     };
-    state.conn_map.write().await.get_mut(&user.id).unwrap().send(serde_json::to_string(&rsp)?).await?;
-    Ok(())
+    let rsp = ServerEvent::ReactionChanged(RspReactionChanged {
+        room_id: msg.room_id,
+        msg_id: msg.id,
+        emoji: emoji.to_string(),
+        user_id: user.id,
+        added: add,
+        count: count_reaction(&state.pool, msg.id, emoji).await?,
+    });
+    // 重复添加或删除不存在的回应时只回复自己
+    if changed {
+        ctx.reply_and_broadcast(&state, msg.room_id, &rsp).await
+    } else {
+        ctx.reply(&state, &rsp).await
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ReqSendMsg {
-    room_id: i32,
-    msg: String,
+async fn thread_msgs(state: Arc<ChatState>, req: ReqThreadMsgs, ctx: &CmdCtx<'_>) -> Result<()> {
+    let mut root = get_chat_msg_by_id(&state.pool, req.root_id).await
+        .map_err(|_| ErrorCode::MsgNotFound.error("message not found"))?;
+    // 传入的是话题里的某条回复时，取它所在话题的根消息
+    if let Some(thread_id) = root.thread_id {
+        root = get_chat_msg_by_id(&state.pool, thread_id).await
+            .map_err(|_| ErrorCode::MsgNotFound.error("message not found"))?;
+    }
+    check_room(&state, root.room_id, ctx.user.id, Permission::ReadHistory).await?;
+    let limit = req.limit.map_or(DEFAULT_PAGE_SIZE, |limit| limit as usize).clamp(1, MAX_PAGE_SIZE);
+    let (msgs, has_more) = thread_page_before(&state, root.id, req.before.or(req.last_id), limit).await?;
+    let root = client_msgs(&state, ctx.user.id, vec![root]).await?.pop()
+        .ok_or_else(|| ErrorCode::MsgNotFound.error("message not found"))?;
+    let msgs = client_msgs(&state, ctx.user.id, msgs).await?;
+    ctx.reply(&state, &ServerEvent::RspThreadMsgs(RspThreadMsgs { root, msgs, has_more })).await
 }
-async fn send_msg(state: Arc<ChatState>, msg: ChatCammand, user: &User) -> Result<()> {
-    let req: ReqSendMsg = serde_json::from_str(&msg.data)?;
+
+async fn send_msg(state: Arc<ChatState>, req: ReqSendMsg, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
     let room = get_room(&state.pool, req.room_id).await.ok_or_else(
-        || anyhow!("room not found")
+        || ErrorCode::RoomNotFound.error("room not found")
     )?;
-    let new_msg = create_chat_msg(&state.pool, room.id, &req.msg, user.id).await?;
-    let rsp = ChatCammand {
-        cmd: "RspSendMsg".to_string(),
-        data: serde_json::to_string(&new_msg)?,
-    };
-    let mut locked = state.conn_map.write().await;
-    locked.get_mut(&user.id).unwrap().send(serde_json::to_string(&rsp)?).await?;
-    let members: Vec<u64> = serde_json::from_str(&room.members)?;
-    members.iter().for_each(|member| {
-        if *member != user.id {
-            if let Some(sender) = locked.get_mut(member) {
-                let _ = sender.send(serde_json::to_string(&rsp).unwrap());
+    let reply_to = match req.reply_to {
+        Some(reply_to) => {
+            let target = get_chat_msg_by_id(&state.pool, reply_to).await
+                .map_err(|_| ErrorCode::MsgNotFound.error("reply target not found"))?;
+            if target.room_id != room.id {
+                return Err(ErrorCode::BadRequest.error("reply target is in another room"));
             }
+            Some(target)
         }
-    });
+        None => None,
+    };
+    let msg_type = match req.msg_type {
+        None => MsgType::Text,
+        Some(msg_type) => MsgType::from_i32(msg_type)
+            .ok_or_else(|| ErrorCode::BadRequest.error(format!("invalid msg_type:{}", msg_type)))?,
+    };
+    let attachments = attachment::check_attachments(&state, &req.attachments, user.id).await?;
+    if req.msg.trim().is_empty() && attachments.is_empty() {
+        return Err(ErrorCode::BadRequest.error("message is empty"));
+    }
+    let content = content::prepare_content(msg_type, &req.msg)?;
+    let ids: Vec<i32> = attachments.iter().map(|a| a.id).collect();
+    let new_msg = create_msg_with_attachments(&state.pool, room.id, msg_type, &content, user.id, reply_to.as_ref(), &ids).await?
+        .ok_or_else(|| ErrorCode::AttachmentNotFound.error("attachment has already been sent"))?;
+    // 自己发的消息视为已读
+    update_last_read(&state.pool, room.id, user.id, new_msg.id).await?;
+    typing::clear(&state, room.id, user.id, &user.username).await?;
+    let sent = SentMsg { msg: new_msg.clone(), attachments: attachments.iter().map(attachment::attachment_info).collect(), poll: None };
+    ctx.reply_and_broadcast(&state, room.id, &ServerEvent::RspSendMsg(sent)).await?;
+    // 消息已经发出，提及失败只记日志；卡片内容是 JSON，不解析提及
+    if msg_type == MsgType::Card {
+        return Ok(());
+    }
+    if let Err(e) = mention::notify_mentions(&state, &room, &new_msg).await {
+        error!("notify mentions error:{}", e);
+    }
     Ok(())
 }
 
-pub async fn push_rooms(state: Arc<ChatState>, user: &User) -> Result<()> {
-    let mut rooms = get_rooms_by_member(&state.pool, user.id.to_string().as_str()).await?;
-    let mut public_rooms = get_rooms_by_type(&state.pool, RoomType::Public as i32).await?;
-    rooms.append(&mut public_rooms);
-    let info = RoomInfo { rooms };
-    let rsp = ChatCammand {
-        cmd: "RspRooms".to_string(),
-        data: serde_json::to_string(&info)?,
+async fn mark_read(state: Arc<ChatState>, req: ReqMarkRead, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let members = get_room_members(&state.pool, req.room_id).await?;
+    let member = members.iter().find(|m| m.user_id == user.id)
+        .ok_or_else(|| ErrorCode::NotRoomMember.error("not a member of this room"))?;
+    let msg = get_chat_msg_by_id(&state.pool, req.msg_id).await
+        .map_err(|_| ErrorCode::MsgNotFound.error("message not found"))?;
+    if msg.room_id != req.room_id {
+        return Err(ErrorCode::BadRequest.error("message is in another room"));
+    }
+    let changed = update_last_read(&state.pool, req.room_id, user.id, msg.id).await?;
+    mark_mentions_read_until(&state.pool, user.id, req.room_id, msg.id).await?;
+    let unread = count_unread(&state.pool, user.id).await?.into_iter()
+        .find(|(room_id, _)| *room_id == req.room_id)
+        .map(|(_, count)| count)
+        .unwrap_or(0);
+    let msg_id = msg.id.max(member.last_read_id);
+    let rsp = ServerEvent::RspMarkRead(RspMarkRead { room_id: req.room_id, msg_id, unread });
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await?;
+    if changed && members.len() <= READ_RECEIPT_MAX_MEMBERS {
+        let receipt = ServerEvent::ReadReceipt(RspReadReceipt { room_id: req.room_id, user_id: user.id, msg_id });
+        for other in members.iter().filter(|m| m.user_id != user.id) {
+            send_to(&state, other.user_id, &receipt).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn edit_msg(state: Arc<ChatState>, req: ReqEditMsg, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    if req.msg.trim().is_empty() {
+        return Err(ErrorCode::BadRequest.error("message is empty, use DeleteMsg instead"));
+    }
+    let msg = get_chat_msg_by_id(&state.pool, req.msg_id).await
+        .map_err(|_| ErrorCode::MsgNotFound.error("message not found"))?;
+    if msg.deleted {
+        return Err(ErrorCode::MsgNotFound.error("message has been deleted"));
+    }
+    if msg.sender != user.id {
+        return Err(ErrorCode::Forbidden.error("only the sender can edit this message"));
+    }
+    check_room(&state, msg.room_id, user.id, Permission::SendMsg).await?;
+    let msg_type = MsgType::from_i32(msg.msg_type).unwrap_or(MsgType::Text);
+    let content = content::prepare_content(msg_type, &req.msg)?;
+    let new_msg = edit_chat_msg(&state.pool, &msg, &content, user.id).await?;
+    ctx.reply_and_broadcast(&state, msg.room_id, &ServerEvent::MsgEdited(new_msg)).await
+}
+
+async fn delete_msg(state: Arc<ChatState>, req: ReqDeleteMsg, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let msg = get_chat_msg_by_id(&state.pool, req.msg_id).await
+        .map_err(|_| ErrorCode::MsgNotFound.error("message not found"))?;
+    if msg.deleted {
+        return Err(ErrorCode::MsgNotFound.error("message has been deleted"));
+    }
+    // 自己的消息可以撤回，房间管理员可以撤回任何人的消息
+    let perm = if msg.sender == user.id { Permission::ReadHistory } else { Permission::DeleteAnyMsg };
+    let (_, member) = check_room(&state, msg.room_id, user.id, perm).await?;
+    if member.is_none() {
+        return Err(ErrorCode::NotRoomMember.error("not a member of this room"));
+    }
+    delete_chat_msg(&state.pool, &msg, user.id).await?;
+    let rsp = ServerEvent::MsgDeleted(RspMsgDeleted { room_id: msg.room_id, msg_id: msg.id });
+    ctx.reply_and_broadcast(&state, msg.room_id, &rsp).await
+}
+
+async fn leave_room(state: Arc<ChatState>, req: ReqLeaveRoom, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let room = get_room(&state.pool, req.room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    let member = get_room_member(&state.pool, room.id, user.id).await
+        .ok_or_else(|| ErrorCode::NotRoomMember.error("not a member of this room"))?;
+    remove_room_member(&state.pool, room.id, user.id).await?;
+    // owner 离开时把房间交给最早加入的 admin，没有 admin 则交给最早加入的成员
+    if member.role() == RoomRole::Owner {
+        let rest = get_room_members(&state.pool, room.id).await?;
+        let heir = rest.iter().find(|m| m.role() == RoomRole::Admin).or(rest.first());
+        if let Some(heir) = heir {
+            update_member_role(&state.pool, room.id, heir.user_id, RoomRole::Owner).await?;
+        }
+    }
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await?;
+    post_system_msg(&state, &room, &format!("{} 离开了房间", user.username)).await
+}
+
+async fn remove_member(state: Arc<ChatState>, req: ReqRemoveMember, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    if req.user_id == user.id {
+        return Err(ErrorCode::BadRequest.error("use LeaveRoom to leave a room"));
+    }
+    let (room, operator) = check_room(&state, req.room_id, user.id, Permission::RemoveMember).await?;
+    let target = get_room_member(&state.pool, room.id, req.user_id).await
+        .ok_or_else(|| ErrorCode::NotRoomMember.error("user is not a member of this room"))?;
+    // owner 可以移除任何人，admin 只能移除普通成员和只读成员
+    let allowed = match operator.map(|m| m.role()) {
+        Some(RoomRole::Owner) => true,
+        Some(RoomRole::Admin) => matches!(target.role(), RoomRole::Member | RoomRole::ReadOnly),
+        _ => false,
+    };
+    if !allowed {
+        return Err(ErrorCode::Forbidden.error("no permission to remove this member"));
+    }
+    remove_room_member(&state.pool, room.id, req.user_id).await?;
+    push_rooms(state.clone(), req.user_id).await?;
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    let target_name = get_user(&state.pool, req.user_id).await
+        .map(|u| u.username)
+        .unwrap_or_else(|| req.user_id.to_string());
+    post_system_msg(&state, &room, &format!("{} 被 {} 移出了房间", target_name, user.username)).await
+}
+
+async fn rename_room(state: Arc<ChatState>, req: ReqRenameRoom, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let room_name = req.room_name.trim();
+    if room_name.is_empty() {
+        return Err(ErrorCode::BadRequest.error("room name is empty"));
+    }
+    let room = get_room(&state.pool, req.room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    update_room_name(&state.pool, room.id, room_name).await?;
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    for member in get_room_member_ids(&state.pool, room.id).await? {
+        if member != user.id {
+            push_rooms(state.clone(), member).await?;
+        }
+    }
+    ctx.push_other_conns(&state, &rsp).await?;
+    post_system_msg(&state, &room, &format!("{} 将房间名改为 {}", user.username, room_name)).await
+}
+
+async fn set_member_role(state: Arc<ChatState>, req: ReqSetMemberRole, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let role = match RoomRole::from_i32(req.role) {
+        Some(RoomRole::Owner) | None => return Err(ErrorCode::BadRequest.error(format!("invalid role:{}", req.role))),
+        Some(role) => role,
     };
-    state.conn_map.write().await.get_mut(&user.id).unwrap().send(serde_json::to_string(&rsp)?).await?;
+    if req.user_id == user.id {
+        return Err(ErrorCode::BadRequest.error("can not change your own role"));
+    }
+    let room = get_room(&state.pool, req.room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    get_room_member(&state.pool, room.id, req.user_id).await
+        .ok_or_else(|| ErrorCode::NotRoomMember.error("user is not a member of this room"))?;
+    update_member_role(&state.pool, room.id, req.user_id, role).await?;
+    push_rooms(state.clone(), req.user_id).await?;
+    ctx.reply(&state, &rooms_event(&state, user.id).await?).await
+}
+
+async fn delete_room(state: Arc<ChatState>, req: ReqDeleteRoom, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let members = get_room_member_ids(&state.pool, req.room_id).await?;
+    room_dao::delete_room(&state.pool, req.room_id).await?;
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await?;
+    for member in members {
+        if member != user.id {
+            push_rooms(state.clone(), member).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 以系统身份往房间里发一条消息并推送给在线成员
+pub(crate) async fn post_system_msg(state: &ChatState, room: &Room, text: &str) -> Result<()> {
+    let new_msg = create_chat_msg(&state.pool, room.id, MsgType::System, text, SYSTEM_SENDER).await?;
+    broadcast_room(state, room.id, &ServerEvent::RspSendMsg(SentMsg { msg: new_msg, attachments: vec![], poll: None })).await
+}
+
+/// 推送给房间的所有成员
+pub async fn broadcast_room(state: &ChatState, room_id: i32, event: &ServerEvent) -> Result<()> {
+    for member in get_room_member_ids(&state.pool, room_id).await? {
+        send_to(state, member, event).await?;
+    }
+    Ok(())
+}
+
+async fn open_direct(state: Arc<ChatState>, req: ReqOpenDirect, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    if req.user_id == user.id {
+        return Err(ErrorCode::BadRequest.error("can not open a direct room with yourself"));
+    }
+    get_user(&state.pool, req.user_id).await.ok_or_else(|| ErrorCode::UserNotFound.error("user not found"))?;
+    let (room, created) = match get_direct_room(&state.pool, user.id, req.user_id).await {
+        Some(room) => (room, false),
+        None => (create_direct_room(&state.pool, RoomType::Private as i32, user.id, req.user_id).await?, true),
+    };
+    // 离开过私聊房间的一方重新打开时恢复成员身份
+    for member in [user.id, req.user_id] {
+        add_room_member(&state.pool, room.id, member, RoomRole::Member).await?;
+    }
+    let item = room_items(&state, user.id, vec![room]).await?.pop()
+        .ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    ctx.reply(&state, &ServerEvent::RspOpenDirect(item)).await?;
+    if created {
+        ctx.push_other_conns(&state, &rooms_event(&state, user.id).await?).await?;
+        push_rooms(state.clone(), req.user_id).await?;
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// 房间的展示名，私聊房间用对方用户名：room_id -> (display_name, peer_id)
+pub(crate) async fn display_names(state: &ChatState, user_id: u64, rooms: &[Room]) -> Result<HashMap<i32, (String, Option<u64>)>> {
+    let peers: HashMap<i32, u64> = get_direct_peers(&state.pool, user_id).await?.into_iter().collect();
+    let peer_ids: Vec<u64> = rooms.iter().filter_map(|room| peers.get(&room.id).copied()).collect();
+    let names: HashMap<u64, String> = get_user_in_id(&state.pool, &peer_ids).await?.into_iter()
+        .map(|user| (user.id, user.username))
+        .collect();
+    Ok(rooms.iter().map(|room| {
+        let peer_id = peers.get(&room.id).copied();
+        let display_name = peer_id
+            .and_then(|peer| names.get(&peer).cloned())
+            .unwrap_or_else(|| room.room_name.clone());
+        (room.id, (display_name, peer_id))
+    }).collect())
+}
+
+/// 补充房间的展示信息、未读数和最后一条消息
+async fn room_items(state: &ChatState, user_id: u64, rooms: Vec<Room>) -> Result<Vec<RoomItem>> {
+    let mut names = display_names(state, user_id, &rooms).await?;
+    let unread: HashMap<i32, i64> = count_unread(&state.pool, user_id).await?.into_iter().collect();
+    let room_ids: Vec<i32> = rooms.iter().map(|room| room.id).collect();
+    let last_msgs = get_last_msgs(&state.pool, &room_ids).await?;
+    let mut last_msgs: HashMap<i32, ClientChatMsg> = client_msgs(state, user_id, last_msgs).await?.into_iter()
+        .map(|msg| (msg.msg.room_id, msg))
+        .collect();
+    Ok(rooms.into_iter().map(|room| {
+        let (display_name, peer_id) = names.remove(&room.id).unwrap_or_else(|| (room.room_name.clone(), None));
+        let unread = unread.get(&room.id).copied().unwrap_or(0);
+        let last_msg = last_msgs.remove(&room.id);
+        RoomItem { room, display_name, peer_id, unread, last_msg }
+    }).collect())
+}
+
+pub(crate) async fn rooms_event(state: &ChatState, user_id: u64) -> Result<ServerEvent> {
+    let mut rooms = get_rooms_by_member(&state.pool, user_id).await?;
+    let public_rooms = get_rooms_by_type(&state.pool, RoomType::Public as i32).await?;
+    for room in public_rooms {
+        if !rooms.iter().any(|r| r.id == room.id) {
+            rooms.push(room);
+        }
+    }
+    let rooms = room_items(state, user_id, rooms).await?;
+    Ok(ServerEvent::RspRooms(RoomInfo { rooms }))
+}
+
+pub async fn push_rooms(state: Arc<ChatState>, user_id: u64) -> Result<()> {
+    send_to(&state, user_id, &rooms_event(&state, user_id).await?).await
+}
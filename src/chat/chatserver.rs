@@ -1,32 +1,111 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex}, time::Duration};
 
 use anyhow::Result;
 use futures::{SinkExt, StreamExt};
-use log::{info, error};
-use serde::{Deserialize, Serialize};
+use log::{info, error, warn};
 use sqlx::{MySql, Pool};
-use tokio::{net::{TcpListener, TcpStream}, sync::RwLock};
+use tokio::{net::{TcpListener, TcpStream}, sync::{mpsc::{error::TrySendError, Receiver}, RwLock}, time::{interval, Instant, Interval, MissedTickBehavior}};
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
-use crate::{chat::chatcmd::{hand_msg, push_rooms, ChatCammand}, dao::user_dao, web::jwt};
+use crate::{chat::{chatcmd::{hand_msg, push_rooms, send_error, CmdCtx}, poll, presence, protocol::{decode_client_frame, peek_str, to_legacy, ClientFrame, FrameKind, ServerEvent}, typing::TypingMap}, dao::user_dao, models::user::User, web::{common::ErrorCode, jwt}};
 
-type ConnSender = tokio::sync::mpsc::Sender<String>;
-type ConnMap = Arc<RwLock<HashMap<u64, ConnSender>>>;
+pub type ConnSender = tokio::sync::mpsc::Sender<String>;
+/// 用户 id -> (连接 id -> 发送队列)，同一用户可以多端同时在线
+type ConnMap = Arc<RwLock<HashMap<u64, HashMap<u64, ConnSender>>>>;
+
+/// 心跳配置：每隔 interval 向连接发送一次 Ping，超过 idle_timeout 没有收到任何帧就断开
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+impl Heartbeat {
+    /// 校验配置：interval 必须大于 0，idle_timeout 必须大于 interval，否则每个连接都会被判定为空闲
+    pub fn new(interval: Duration, idle_timeout: Duration) -> Result<Self> {
+        if interval.is_zero() {
+            return Err(anyhow::anyhow!("heartbeat interval must be greater than 0"));
+        }
+        if idle_timeout <= interval {
+            return Err(anyhow::anyhow!("idle timeout ({:?}) must be greater than heartbeat interval ({:?})", idle_timeout, interval));
+        }
+        Ok(Self { interval, idle_timeout })
+    }
+
+    /// 定时器，第一次触发在一个周期之后
+    pub fn ticker(&self) -> Interval {
+        let mut ticker = interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.reset();
+        ticker
+    }
+
+    pub fn is_idle(&self, last_active: Instant) -> bool {
+        last_active.elapsed() >= self.idle_timeout
+    }
+}
+
+/// 发送队列配置：每个连接最多缓存 capacity 帧，队列持续满超过 stall_timeout 才摘掉连接
+#[derive(Debug, Clone, Copy)]
+pub struct SendQueue {
+    pub capacity: usize,
+    pub stall_timeout: Duration,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            stall_timeout: Duration::from_secs(10),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ChatState {
     pub conn_map: ConnMap,
     pub pool: Pool<MySql>,
+    next_conn_id: AtomicU64,
+    pub typing: TypingMap,
+    pub heartbeat: Heartbeat,
+    pub send_queue: SendQueue,
+    /// 连接 id -> 发送队列开始持续满的时间
+    stalled: Mutex<HashMap<u64, Instant>>,
 }
 
-pub async fn start_chat_server(port: u16, pool: Pool<MySql>) -> Result<Arc<ChatState>> {
-    let state = ChatState {
-        conn_map: Arc::new(RwLock::new(HashMap::new())),
-        pool: pool,
-    };
-    let state = Arc::new(state);
+impl ChatState {
+    pub fn new(pool: Pool<MySql>, heartbeat: Heartbeat, send_queue: SendQueue) -> Self {
+        Self {
+            conn_map: Arc::new(RwLock::new(HashMap::new())),
+            pool,
+            next_conn_id: AtomicU64::new(1),
+            typing: TypingMap::default(),
+            heartbeat,
+            send_queue,
+            stalled: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn gen_conn_id(&self) -> u64 {
+        self.next_conn_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+pub async fn start_chat_server(port: u16, pool: Pool<MySql>, heartbeat: Heartbeat, send_queue: SendQueue) -> Result<Arc<ChatState>> {
+    let state = Arc::new(ChatState::new(pool, heartbeat, send_queue));
     let result = state.clone();
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
+    // 重启前设置了截止时间的投票需要重新安排关闭
+    poll::schedule_pending(state.clone()).await?;
     tokio::spawn(async move {
         info!("start chat server on {}", port);
         while let Ok((stream, addr)) = listener.accept().await {
@@ -46,10 +125,154 @@ pub async fn start_chat_server(port: u16, pool: Pool<MySql>) -> Result<Arc<ChatS
     Ok(result)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AuthUser {
-    id: u64,
-    username: String,
+/// 校验连接上来的第一帧 token，返回对应用户
+pub async fn auth_user(state: &ChatState, token: &str) -> Result<User> {
+    if token.is_empty() {
+        return Err(ErrorCode::Unauthorized.error("auth user error"));
+    }
+    let claims = jwt::validate_jwt(token).map_err(|e| ErrorCode::Unauthorized.error(e.to_string()))?;
+    user_dao::get_user(&state.pool, claims.sub).await.ok_or_else(|| ErrorCode::UserNotFound.error("user not found"))
+}
+
+/// 登记一个已认证的连接并推送房间列表，返回连接 id 和该连接的待发送消息队列
+pub async fn open_conn(state: Arc<ChatState>, user: &User) -> Result<(u64, Receiver<String>)> {
+    let (sender, receiver) = tokio::sync::mpsc::channel::<String>(state.send_queue.capacity);
+    let conn_id = state.gen_conn_id();
+    let first = {
+        let mut locked = state.conn_map.write().await;
+        let conns = locked.entry(user.id).or_default();
+        conns.insert(conn_id, sender);
+        conns.len() == 1
+    };
+    push_rooms(state.clone(), user.id).await?;
+    if first {
+        if let Err(e) = presence::went_online(&state, user).await {
+            error!("update presence error:{}", e);
+        }
+    }
+    Ok((conn_id, receiver))
+}
+
+/// 只移除关闭的这一个连接，用户的其他设备不受影响；连接可能已经被 [`drop_conn`] 摘掉
+pub async fn close_conn(state: &ChatState, user: &User, conn_id: u64) {
+    let last = {
+        let mut locked = state.conn_map.write().await;
+        match locked.get_mut(&user.id) {
+            Some(conns) => {
+                conns.remove(&conn_id);
+                let last = conns.is_empty();
+                if last {
+                    locked.remove(&user.id);
+                }
+                last
+            }
+            // 连接已被 drop_conn 摘掉，且用户没有剩下的连接
+            None => true,
+        }
+    };
+    state.stalled.lock().unwrap().remove(&conn_id);
+    if last {
+        if let Err(e) = presence::went_offline(state, user).await {
+            error!("update presence error:{}", e);
+        }
+    }
+}
+
+/// 摘掉发送队列卡住的连接，不再往它推送；用户没有剩下的连接时一并移除，在线判断立即生效，
+/// 下线通知等读循环退出时由 [`close_conn`] 发出
+pub async fn drop_conn(state: &ChatState, user_id: u64, conn_id: u64) {
+    let mut locked = state.conn_map.write().await;
+    if let Some(conns) = locked.get_mut(&user_id) {
+        conns.remove(&conn_id);
+        if conns.is_empty() {
+            locked.remove(&user_id);
+        }
+    }
+}
+
+/// 不等待地放进连接的发送队列，一个慢连接不能拖住发送方。
+/// 队列满时丢弃这一帧并记下开始时间，持续满超过 stall_timeout 才摘掉连接，返回连接是否已被摘掉
+pub async fn push_conn(state: &ChatState, user_id: u64, conn_id: u64, sender: &ConnSender, data: String) -> bool {
+    match sender.try_send(data) {
+        Ok(_) => {
+            state.stalled.lock().unwrap().remove(&conn_id);
+            false
+        }
+        Err(TrySendError::Full(_)) => {
+            let stalled = {
+                let mut stalled = state.stalled.lock().unwrap();
+                let since = *stalled.entry(conn_id).or_insert_with(Instant::now);
+                since.elapsed() >= state.send_queue.stall_timeout
+            };
+            if stalled {
+                warn!("send queue stalled, drop conn:{}, {}", user_id, conn_id);
+                drop_conn(state, user_id, conn_id).await;
+            }
+            stalled
+        }
+        // 写任务已经退出，读循环随后会关闭连接
+        Err(TrySendError::Closed(_)) => false,
+    }
+}
+
+/// 服务端主动探测连接是否还活着，客户端应回复 Pong。
+/// 不等待发送队列：队列卡住的连接会被摘掉，连接已被摘掉或队列已关闭都视为失效，返回错误
+pub async fn ping_conn(state: &ChatState, user_id: u64, conn_id: u64) -> Result<()> {
+    let sender = state.conn_map.read().await
+        .get(&user_id)
+        .and_then(|conns| conns.get(&conn_id))
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("conn has been dropped"))?;
+    if sender.is_closed() {
+        return Err(anyhow::anyhow!("conn send queue closed"));
+    }
+    if push_conn(state, user_id, conn_id, &sender, ServerEvent::Ping.encode(FrameKind::Push, None)?).await {
+        return Err(anyhow::anyhow!("conn send queue stalled"));
+    }
+    Ok(())
+}
+
+/// 连接使用的帧格式，由客户端最近一次能看出格式的帧决定，默认新格式。
+/// 读循环更新它，写任务按它决定是否把发出的帧转成旧格式
+#[derive(Debug, Clone, Default)]
+pub struct ConnFormat(Arc<AtomicBool>);
+
+impl ConnFormat {
+    fn update(&self, frame: &ClientFrame) {
+        if let Some(legacy) = frame.legacy {
+            self.0.store(legacy, Ordering::Relaxed);
+        }
+    }
+
+    /// 按连接的格式转换即将写出的帧
+    pub fn outgoing(&self, frame: String) -> String {
+        if !self.0.load(Ordering::Relaxed) {
+            return frame;
+        }
+        match to_legacy(&frame) {
+            Ok(legacy) => legacy,
+            Err(e) => {
+                error!("convert to legacy frame error:{}", e);
+                frame
+            }
+        }
+    }
+}
+
+/// 处理客户端发来的一帧文本，TCP 和 WebSocket 共用
+pub async fn hand_text(state: Arc<ChatState>, user: &User, conn_id: u64, format: &ConnFormat, logic_msg: &str) {
+    let frame = decode_client_frame(logic_msg);
+    match frame {
+        Ok(frame) => {
+            format.update(&frame);
+            hand_msg(state, frame, user, conn_id).await;
+        },
+        Err(e) => {
+            info!("recv msg error:{}, err:{:?}", logic_msg, e);
+            let ctx = CmdCtx { user, conn_id, req_id: peek_str(logic_msg, "req_id") };
+            send_error(&state, &ctx, &e, peek_str(logic_msg, "cmd")).await;
+        },
+    }
 }
 
 async fn hand_connect(stream: TcpStream, state: Arc<ChatState>, addr: SocketAddr) -> Result<()> {  
@@ -58,30 +281,26 @@ async fn hand_connect(stream: TcpStream, state: Arc<ChatState>, addr: SocketAddr
     let mut framed = FramedRead::new(read, LengthDelimitedCodec::new());
     //auth user
     let user;
-    if let Some(data) = framed.next().await {
+    // 连上之后迟迟不发 token 的连接同样按空闲超时处理
+    let first = tokio::time::timeout(state.heartbeat.idle_timeout, framed.next()).await
+        .map_err(|_| anyhow::anyhow!("auth timeout {}", addr))?;
+    if let Some(data) = first {
         info!("auth user:{}", addr);
         let auth_msg = String::from_utf8(data?.to_vec())?;
         info!("auth_msg {}", auth_msg);
-        if auth_msg.is_empty() {
-            info!("auth user error1 {}", addr);
-            return Err(anyhow::anyhow!("auth user error"));
-        }
-        let claims = jwt::validate_jwt(auth_msg.as_str())?;
-        user = user_dao::get_user(&state.pool, claims.sub).await.ok_or_else(|| anyhow::anyhow!("user not found"))?;
+        user = auth_user(&state, &auth_msg).await?;
     } else {
         error!("auth user error2 {}", addr);
         return Err(anyhow::anyhow!("read line from stream error"));
     }
     info!("auth user success:{}, {}", user.username, addr);
-    let (sender, receiver) = tokio::sync::mpsc::channel::<String>(10);
-    state.conn_map.write().await
-        .insert(user.id, sender);
-    push_rooms(state.clone(), &user).await?;
+    let (conn_id, mut receiver) = open_conn(state.clone(), &user).await?;
+    let format = ConnFormat::default();
+    let writer_format = format.clone();
     tokio::spawn(async move {
-        let mut receiver = receiver;
         let mut frame_writer = FramedWrite::new(write, LengthDelimitedCodec::new());
         while let Some(msg) = receiver.recv().await {
-            match frame_writer.send(bytes::Bytes::from(msg)).await {
+            match frame_writer.send(bytes::Bytes::from(writer_format.outgoing(msg))).await {
                 Ok(_) => {}
                 Err(e) => {
                     info!("send msg error:{}", e);
@@ -89,22 +308,38 @@ async fn hand_connect(stream: TcpStream, state: Arc<ChatState>, addr: SocketAddr
             }
         }
     });
-    loop {
-        if let Some(data) = framed.next().await {
-            let logic_msg = String::from_utf8(data?.to_vec())?;
-            let cmd = serde_json::from_str::<ChatCammand>(&logic_msg);
-            match cmd {
-                Ok(chatcmd) => {
-                    hand_msg(state.clone(), chatcmd, &user).await;
-                },
-                Err(e) => {
-                    info!("recv msg error:{}, err:{:?}", logic_msg, e);
-                },
+    let heartbeat = state.heartbeat;
+    let mut ticker = heartbeat.ticker();
+    let mut last_active = Instant::now();
+    let r = loop {
+        tokio::select! {
+            data = framed.next() => {
+                let logic_msg = match data {
+                    Some(Ok(data)) => String::from_utf8(data.to_vec()),
+                    Some(Err(e)) => break Err(e.into()),
+                    None => break Err(anyhow::anyhow!("read line from stream error")),
+                };
+                match logic_msg {
+                    Ok(logic_msg) => {
+                        last_active = Instant::now();
+                        hand_text(state.clone(), &user, conn_id, &format, &logic_msg).await;
+                    }
+                    Err(e) => break Err(e.into()),
+                }
+            }
+            _ = ticker.tick() => {
+                if heartbeat.is_idle(last_active) {
+                    info!("idle timeout:{}, {}", user.username, addr);
+                    break Ok(());
+                }
+                if let Err(e) = ping_conn(&state, user.id, conn_id).await {
+                    error!("ping error:{}, {}, {}", user.username, addr, e);
+                    break Err(e);
+                }
             }
-        } else {
-            info!("close connect:{}, {}", user.username, addr);
-            state.conn_map.write().await.remove(&user.id);
-            return Err(anyhow::anyhow!("read line from stream error"));
         }
-    }
-}
\ No newline at end of file
+    };
+    info!("close connect:{}, {}", user.username, addr);
+    close_conn(&state, &user, conn_id).await;
+    r
+}
@@ -0,0 +1,203 @@
+//! 消息内容
+//!
+//! 按消息类型检查并整理客户端发来的内容：markdown 去掉原始 HTML 和危险链接，
+//! 卡片解析后重新序列化，系统通知只能由服务端发出。
+
+use std::{cmp::Reverse, ops::Range};
+
+use anyhow::Result;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+use crate::{models::chatmsg::MsgType, web::common::ErrorCode};
+
+use super::protocol::MsgCard;
+
+const MAX_CARD_FIELDS: usize = 20;
+const MAX_CARD_BUTTONS: usize = 5;
+/// 链接允许的协议，不带协议的相对链接也允许
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+/// 反复整理直到不再变化的最多轮数
+const MAX_SANITIZE_PASSES: usize = 8;
+
+/// 检查消息内容，返回实际保存的内容
+pub fn prepare_content(msg_type: MsgType, msg: &str) -> Result<String> {
+    match msg_type {
+        MsgType::Text => Ok(msg.to_string()),
+        MsgType::Markdown => Ok(sanitize_markdown(msg)),
+        MsgType::Card => {
+            let card: MsgCard = serde_json::from_str(msg)
+                .map_err(|e| ErrorCode::BadRequest.error(format!("invalid card:{}", e)))?;
+            Ok(serde_json::to_string(&check_card(card)?)?)
+        }
+        MsgType::System => Err(ErrorCode::BadRequest.error("system notices can only be sent by the server")),
+        MsgType::Poll => Err(ErrorCode::BadRequest.error("use CreatePoll to create a poll")),
+    }
+}
+
+fn check_card(mut card: MsgCard) -> Result<MsgCard> {
+    if card.title.trim().is_empty() {
+        return Err(ErrorCode::BadRequest.error("card title is empty"));
+    }
+    if card.fields.len() > MAX_CARD_FIELDS || card.buttons.len() > MAX_CARD_BUTTONS {
+        return Err(ErrorCode::BadRequest.error(format!(
+            "a card can have at most {} fields and {} buttons", MAX_CARD_FIELDS, MAX_CARD_BUTTONS
+        )));
+    }
+    for button in &card.buttons {
+        if button.url.is_none() && button.action.is_none() {
+            return Err(ErrorCode::BadRequest.error("card button needs a url or an action"));
+        }
+        if button.url.as_deref().is_some_and(|url| !is_safe_url(url)) {
+            return Err(ErrorCode::BadRequest.error("card button url is not allowed"));
+        }
+    }
+    card.text = card.text.map(|text| sanitize_markdown(&text));
+    Ok(card)
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim();
+    // 第一个 / ? # 之前的冒号才是协议分隔符
+    let head = url.split(['/', '?', '#']).next().unwrap_or_default();
+    match head.split_once(':') {
+        Some((scheme, _)) => SAFE_SCHEMES.iter().any(|s| s.eq_ignore_ascii_case(scheme)),
+        // 渲染时实体（&#58;）、百分号转义和反斜杠转义都会被解码，可能解出冒号，协议之前出现这些字符一律拒绝
+        None => !head.chars().any(|c| matches!(c, '&' | '%' | '\\') || c.is_whitespace() || c.is_control()),
+    }
+}
+
+/// 过滤 markdown：原始 HTML 里的 `<` 转义成实体，使它不生效；
+/// 链接、图片和链接定义里不安全的地址替换成 `#`。按 CommonMark 解析，跨行的地址、引用和列表里的链接定义都能识别。
+/// 转义 HTML 后原本被 HTML 块吞掉的内容会重新按 markdown 解析，所以反复整理直到不再变化
+pub fn sanitize_markdown(text: &str) -> String {
+    let mut text = text.to_string();
+    for _ in 0..MAX_SANITIZE_PASSES {
+        let next = sanitize_pass(&text);
+        if next == text {
+            return text;
+        }
+        text = next;
+    }
+    // 一直不收敛的输入让所有 HTML 和链接都失效
+    text.replace('<', "&lt;").replace('[', "\\[")
+}
+
+/// 对原文某一段的改写
+enum Edit {
+    /// 原始 HTML
+    Html,
+    /// 地址不安全的链接或图片，保留 inner 范围里的文字
+    Link { inner: Range<usize>, image: bool },
+    /// 地址不安全的链接定义
+    Def(String),
+}
+
+/// 还没结束的链接或图片
+struct OpenLink {
+    span: Range<usize>,
+    image: bool,
+    unsafe_dest: bool,
+    /// 链接文字的范围
+    inner: Option<Range<usize>>,
+}
+
+/// 解析一遍，按原文位置改写不安全的部分，其余内容原样保留
+fn sanitize_pass(text: &str) -> String {
+    let mut edits: Vec<(Range<usize>, Edit)> = vec![];
+    let mut links: Vec<OpenLink> = vec![];
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut parser = Parser::new_ext(text, options).into_offset_iter();
+    for (event, range) in parser.by_ref() {
+        if matches!(event, Event::End(TagEnd::Link | TagEnd::Image)) {
+            if let Some(link) = links.pop().filter(|link| link.unsafe_dest) {
+                let inner = link.inner.unwrap_or(link.span.start..link.span.start);
+                edits.push((link.span, Edit::Link { inner, image: link.image }));
+            }
+        }
+        // 链接里的内容（包括嵌套的图片）都算进外层链接的文字
+        for link in links.iter_mut() {
+            link.inner = Some(match link.inner.take() {
+                Some(inner) => inner.start.min(range.start)..inner.end.max(range.end),
+                None => range.clone(),
+            });
+        }
+        match event {
+            Event::Start(Tag::Link { dest_url, .. }) => links.push(OpenLink { span: range, image: false, unsafe_dest: !is_safe_url(&dest_url), inner: None }),
+            Event::Start(Tag::Image { dest_url, .. }) => links.push(OpenLink { span: range, image: true, unsafe_dest: !is_safe_url(&dest_url), inner: None }),
+            Event::Html(_) | Event::InlineHtml(_) => edits.push((range, Edit::Html)),
+            _ => {}
+        }
+    }
+    for (label, def) in parser.reference_definitions().iter() {
+        if !is_safe_url(&def.dest) {
+            edits.push((def.span.clone(), Edit::Def(label.to_string())));
+        }
+    }
+    edits.sort_by_key(|(span, _)| (span.start, Reverse(span.end)));
+    apply_edits(text, 0..text.len(), &edits)
+}
+
+/// 改写 range 范围内的原文，嵌套在链接文字里的改写递归处理
+fn apply_edits(text: &str, range: Range<usize>, edits: &[(Range<usize>, Edit)]) -> String {
+    let mut out = String::with_capacity(range.len());
+    let mut pos = range.start;
+    for (span, edit) in edits {
+        if span.start < pos || span.end > range.end {
+            continue;
+        }
+        out.push_str(&text[pos..span.start]);
+        match edit {
+            Edit::Html => out.push_str(&text[span.clone()].replace('<', "&lt;")),
+            Edit::Link { inner, image } => {
+                if *image {
+                    out.push('!');
+                }
+                out.push('[');
+                out.push_str(&apply_edits(text, inner.clone(), edits));
+                out.push_str("](#)");
+            }
+            Edit::Def(label) => {
+                out.push_str(&format!("[{}]: #", label));
+                // 定义的范围可能带着行尾换行
+                if text[span.clone()].ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+        }
+        pos = span.end;
+    }
+    out.push_str(&text[pos..range.end]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sanitize_markdown() {
+        assert_eq!(sanitize_markdown("**hi** <script>alert(1)</script>"), "**hi** &lt;script>alert(1)&lt;/script>");
+        assert_eq!(sanitize_markdown("`<b>` and ``a ` <i>``"), "`<b>` and ``a ` <i>``");
+        assert_eq!(sanitize_markdown("```html\n<div>\n```\n<div>"), "```html\n<div>\n```\n&lt;div>");
+        assert_eq!(sanitize_markdown("[a](https://x.com/(1)) [b](javascript:alert(1)) ![c](/img.png)"), "[a](https://x.com/(1)) [b](#) ![c](/img.png)");
+        assert_eq!(sanitize_markdown("[x]: JavaScript:alert(1)\n> quote"), "[x]: #\n> quote");
+        assert_eq!(sanitize_markdown("[x](javascript&#58;alert(1)) [y](javascript%3Aalert(1)) [z](javascript\\:alert(1))"), "[x](#) [y](#) [z](#)");
+        assert_eq!(sanitize_markdown("[ok](docs/a.md#part) [q](/search?q=a&b=1)"), "[ok](docs/a.md#part) [q](/search?q=a&b=1)");
+        assert_eq!(sanitize_markdown("[a](\njavascript:alert(1))"), "[a](#)");
+        assert_eq!(sanitize_markdown("[x]:\njavascript:alert(1)\n\n[c][x]"), "[x]: #\n\n[c](#)");
+        assert_eq!(sanitize_markdown("> [x]: javascript:alert(1)"), "> [x]: #");
+        assert_eq!(sanitize_markdown("- [x]: javascript:alert(1)"), "- [x]: #");
+        assert_eq!(sanitize_markdown("[![i](javascript:a)](javascript:b) <javascript:c>"), "[![i](#)](#) [javascript:c](#)");
+        assert_eq!(sanitize_markdown("<div>\n[a](javascript:alert(1))"), "&lt;div>\n[a](#)");
+    }
+
+    #[test]
+    fn test_prepare_card() {
+        let card = r#"{"title":"Deploy","text":"<b>done</b>","buttons":[{"text":"open","url":"https://ci"}]}"#;
+        assert_eq!(prepare_content(MsgType::Card, card).unwrap(), r#"{"title":"Deploy","text":"&lt;b>done&lt;/b>","buttons":[{"text":"open","url":"https://ci"}]}"#);
+        assert!(prepare_content(MsgType::Card, r#"{"title":"x","buttons":[{"text":"a","url":"data:text/html,1"}]}"#).is_err());
+        assert!(prepare_content(MsgType::Card, "not json").is_err());
+        assert!(prepare_content(MsgType::System, "hi").is_err());
+    }
+}
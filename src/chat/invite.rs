@@ -0,0 +1,98 @@
+//! 房间邀请
+//!
+//! 非公共房间只能通过邀请加入：owner/admin 可以直接邀请某个用户，也可以生成可分享的邀请码。
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use sqlx::types::chrono::Local;
+
+use crate::{dao::{invite_dao::{create_invite, get_invite, get_invite_by_code, get_pending_invite, get_pending_invites, update_invite_status}, room_dao::{add_room_member, get_room, get_room_member}, user_dao::get_user}, models::{invite::{InviteStatus, RoomInvite}, room_member::RoomRole}, utils::random::random_hex, web::common::ErrorCode};
+
+use super::{chatcmd::{post_system_msg, rooms_event, send_to, CmdCtx}, chatserver::ChatState, protocol::{InviteInfo, ReqAcceptInvite, ReqCreateInviteCode, ReqDeclineInvite, ReqInviteUser, RspInvites, ServerEvent}};
+
+async fn invite_info(state: &ChatState, invite: &RoomInvite) -> Result<InviteInfo> {
+    let room = get_room(&state.pool, invite.room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    let inviter_name = get_user(&state.pool, invite.inviter).await
+        .map(|u| u.username)
+        .unwrap_or_else(|| "none".to_string());
+    Ok(InviteInfo {
+        id: invite.id,
+        room_id: room.id,
+        room_name: room.room_name,
+        inviter: invite.inviter,
+        inviter_name,
+        code: invite.code.clone(),
+        expires_at: invite.expires_at.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+    })
+}
+
+pub async fn invite_user(state: Arc<ChatState>, req: ReqInviteUser, ctx: &CmdCtx<'_>) -> Result<()> {
+    get_user(&state.pool, req.user_id).await.ok_or_else(|| ErrorCode::UserNotFound.error("user not found"))?;
+    if get_room_member(&state.pool, req.room_id, req.user_id).await.is_some() {
+        return Err(ErrorCode::BadRequest.error("user is already a member of this room"));
+    }
+    // 已经有未处理的邀请时不重复创建
+    let invite = match get_pending_invite(&state.pool, req.room_id, req.user_id).await {
+        Some(invite) => invite,
+        None => create_invite(&state.pool, req.room_id, ctx.user.id, Some(req.user_id), None, None).await?,
+    };
+    let info = invite_info(&state, &invite).await?;
+    ctx.reply(&state, &ServerEvent::RspInvite(info.clone())).await?;
+    send_to(&state, req.user_id, &ServerEvent::Invite(info)).await
+}
+
+pub async fn create_invite_code(state: Arc<ChatState>, req: ReqCreateInviteCode, ctx: &CmdCtx<'_>) -> Result<()> {
+    let code = random_hex(16);
+    let invite = create_invite(&state.pool, req.room_id, ctx.user.id, None, Some(&code), req.expires_in).await?;
+    ctx.reply(&state, &ServerEvent::RspInvite(invite_info(&state, &invite).await?)).await
+}
+
+pub async fn accept_invite(state: Arc<ChatState>, req: ReqAcceptInvite, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    let invite = match (req.invite_id, req.code.as_deref()) {
+        (Some(id), _) => get_invite(&state.pool, id).await
+            .filter(|invite| invite.invitee == Some(user.id)),
+        (None, Some(code)) => get_invite_by_code(&state.pool, code).await,
+        (None, None) => return Err(ErrorCode::BadRequest.error("invite_id or code is required")),
+    }.ok_or_else(|| ErrorCode::InviteNotFound.error("invite not found"))?;
+    if invite.status != InviteStatus::Pending as i32 {
+        return Err(ErrorCode::InviteNotFound.error("invite has been handled"));
+    }
+    if invite.is_expired(Local::now().naive_local()) {
+        return Err(ErrorCode::InviteExpired.error("invite expired"));
+    }
+    let room = get_room(&state.pool, invite.room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    let newly_joined = get_room_member(&state.pool, room.id, user.id).await.is_none();
+    add_room_member(&state.pool, room.id, user.id, RoomRole::Member).await?;
+    // 邀请码可以多人使用，只有点对点的邀请需要标记为已接受
+    if invite.invitee.is_some() {
+        update_invite_status(&state.pool, invite.id, InviteStatus::Accepted).await?;
+    }
+    let rsp = rooms_event(&state, user.id).await?;
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await?;
+    if newly_joined {
+        post_system_msg(&state, &room, &format!("{} 加入了房间", user.username)).await?;
+    }
+    Ok(())
+}
+
+pub async fn decline_invite(state: Arc<ChatState>, req: ReqDeclineInvite, ctx: &CmdCtx<'_>) -> Result<()> {
+    let invite = get_invite(&state.pool, req.invite_id).await
+        .filter(|invite| invite.invitee == Some(ctx.user.id) && invite.status == InviteStatus::Pending as i32)
+        .ok_or_else(|| ErrorCode::InviteNotFound.error("invite not found"))?;
+    update_invite_status(&state.pool, invite.id, InviteStatus::Declined).await?;
+    invites(state, ctx).await
+}
+
+pub async fn invites(state: Arc<ChatState>, ctx: &CmdCtx<'_>) -> Result<()> {
+    let mut infos = vec![];
+    for invite in get_pending_invites(&state.pool, ctx.user.id).await? {
+        // 房间已删除的邀请直接跳过
+        if let Ok(info) = invite_info(&state, &invite).await {
+            infos.push(info);
+        }
+    }
+    ctx.reply(&state, &ServerEvent::RspInvites(RspInvites { invites: infos })).await
+}
@@ -0,0 +1,167 @@
+//! @提及
+//!
+//! 发送消息时解析其中的 `@username`、`@all` 和 `@here`，给被提及的房间成员记录提及并推送 `Mentioned`，
+//! 用户不在查看该房间时也能收到；未读的提及可以通过 `MyMentions` 分页拉取。
+
+use std::{collections::{hash_map::Entry, HashMap}, sync::Arc};
+
+use anyhow::Result;
+
+use crate::{dao::{chatmsg_dao::get_chat_msgs_by_ids, mention_dao::{create_mentions, get_unread_mentions, mark_mentions_read}, room_dao::{get_room, get_room_member_ids}, user_dao::get_users_by_names}, models::{chatmsg::ChatMessage, mention::{Mention, MentionKind}, room::Room}};
+
+use super::{chatcmd::{client_msgs, send_to, CmdCtx}, chatserver::ChatState, protocol::{MentionInfo, ReqMyMentions, ReqReadMentions, RspMentions, ServerEvent}};
+
+/// 一条消息里解析出的提及
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Mentions {
+    pub names: Vec<String>,
+    pub all: bool,
+    pub here: bool,
+}
+
+impl Mentions {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && !self.all && !self.here
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// 解析消息里的提及，`@` 前面是字母数字时（例如邮箱地址）不算提及
+pub fn parse_mentions(text: &str) -> Mentions {
+    let mut mentions = Mentions::default();
+    let mut prev: Option<char> = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '@' && !prev.is_some_and(is_name_char) {
+            let start = i + 1;
+            let mut end = start;
+            while let Some(&(j, n)) = chars.peek() {
+                if !is_name_char(n) {
+                    break;
+                }
+                end = j + n.len_utf8();
+                prev = Some(n);
+                chars.next();
+            }
+            // 句末的点不算用户名的一部分
+            let name = text[start..end].trim_end_matches('.');
+            match name {
+                "" => {}
+                "all" => mentions.all = true,
+                "here" => mentions.here = true,
+                name if !mentions.names.iter().any(|n| n == name) => mentions.names.push(name.to_string()),
+                _ => {}
+            }
+            if end > start {
+                continue;
+            }
+        }
+        prev = Some(c);
+    }
+    mentions
+}
+
+/// 记录新消息里的提及并推送给被提及的成员，发送者自己不会被提及
+pub(crate) async fn notify_mentions(state: &ChatState, room: &Room, msg: &ChatMessage) -> Result<()> {
+    let mentions = parse_mentions(&msg.message);
+    if mentions.is_empty() {
+        return Ok(());
+    }
+    let members = get_room_member_ids(&state.pool, room.id).await?;
+    let mut targets: Vec<(u64, MentionKind)> = vec![];
+    for user in get_users_by_names(&state.pool, &mentions.names).await? {
+        if members.contains(&user.id) {
+            targets.push((user.id, MentionKind::User));
+        }
+    }
+    if mentions.all || mentions.here {
+        let online = state.conn_map.read().await;
+        for member in &members {
+            if targets.iter().any(|(id, _)| id == member) {
+                continue;
+            }
+            if mentions.all {
+                targets.push((*member, MentionKind::All));
+            } else if online.contains_key(member) {
+                targets.push((*member, MentionKind::Here));
+            }
+        }
+    }
+    targets.retain(|(id, _)| *id != msg.sender);
+    let created = create_mentions(&state.pool, msg, &targets).await?;
+    let Some(client_msg) = client_msgs(state, msg.sender, vec![msg.clone()]).await?.pop() else {
+        return Ok(());
+    };
+    for mention in created {
+        let info = MentionInfo {
+            id: mention.id,
+            room_id: room.id,
+            room_name: room.room_name.clone(),
+            kind: mention.kind,
+            msg: client_msg.clone(),
+        };
+        send_to(state, mention.user_id, &ServerEvent::Mentioned(info)).await?;
+    }
+    Ok(())
+}
+
+async fn mention_infos(state: &ChatState, user_id: u64, mentions: Vec<Mention>) -> Result<Vec<MentionInfo>> {
+    let msg_ids: Vec<i32> = mentions.iter().map(|m| m.msg_id).collect();
+    let mut msgs: HashMap<i32, _> = client_msgs(state, user_id, get_chat_msgs_by_ids(&state.pool, &msg_ids).await?).await?
+        .into_iter()
+        .map(|msg| (msg.msg.id, msg))
+        .collect();
+    let mut room_names: HashMap<i32, String> = HashMap::new();
+    for mention in &mentions {
+        if let Entry::Vacant(entry) = room_names.entry(mention.room_id) {
+            entry.insert(get_room(&state.pool, mention.room_id).await.map(|room| room.room_name).unwrap_or_default());
+        }
+    }
+    let mut infos = vec![];
+    for mention in mentions {
+        let Some(msg) = msgs.remove(&mention.msg_id) else {
+            continue;
+        };
+        infos.push(MentionInfo {
+            id: mention.id,
+            room_id: mention.room_id,
+            room_name: room_names[&mention.room_id].clone(),
+            kind: mention.kind,
+            msg,
+        });
+    }
+    Ok(infos)
+}
+
+pub async fn my_mentions(state: Arc<ChatState>, req: ReqMyMentions, ctx: &CmdCtx<'_>) -> Result<()> {
+    let mentions = get_unread_mentions(&state.pool, ctx.user.id, req.last_id).await?;
+    let mentions = mention_infos(&state, ctx.user.id, mentions).await?;
+    ctx.reply(&state, &ServerEvent::RspMentions(RspMentions { mentions })).await
+}
+
+/// 标记已读后返回剩下的第一页未读提及
+pub async fn read_mentions(state: Arc<ChatState>, req: ReqReadMentions, ctx: &CmdCtx<'_>) -> Result<()> {
+    mark_mentions_read(&state.pool, ctx.user.id, req.room_id).await?;
+    my_mentions(state, ReqMyMentions { last_id: None }, ctx).await
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse_mentions() {
+        let mentions = parse_mentions("@alice hi @bob_1, see @alice. mail me at a@b.com @all");
+        assert_eq!(mentions.names, vec!["alice", "bob_1"]);
+        assert!(mentions.all);
+        assert!(!mentions.here);
+        let mentions = parse_mentions("@here 张三 @李四：开会了 @ @");
+        assert_eq!(mentions.names, vec!["李四"]);
+        assert!(mentions.here);
+        assert!(parse_mentions("no mentions, x@y").is_empty());
+    }
+}
@@ -1,3 +1,14 @@
 
 pub mod chatserver;
-pub mod chatcmd;
\ No newline at end of file
+pub mod chatcmd;
+pub mod protocol;
+pub mod permission;
+pub mod invite;
+pub mod mention;
+pub mod typing;
+pub mod presence;
+pub mod sync;
+pub mod search;
+pub mod attachment;
+pub mod content;
+pub mod poll;
\ No newline at end of file
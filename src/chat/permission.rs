@@ -0,0 +1,117 @@
+//! 房间权限
+//!
+//! 每条针对房间的命令在分发前都会先经过 [`check`]，按用户在房间中的角色判断是否允许执行。
+
+use anyhow::Result;
+
+use crate::{dao::room_dao::{get_room, get_room_member}, models::{room::Room, room_member::{RoomMember, RoomRole}}, web::common::ErrorCode};
+
+use super::{chatcmd::RoomType, chatserver::ChatState, protocol::ClientCommand};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ReadHistory,
+    SendMsg,
+    /// 撤回别人的消息
+    DeleteAnyMsg,
+    Invite,
+    RemoveMember,
+    Rename,
+    SetRole,
+    Delete,
+}
+
+impl RoomRole {
+    pub fn can(self, perm: Permission) -> bool {
+        match perm {
+            Permission::ReadHistory => true,
+            Permission::SendMsg => self != RoomRole::ReadOnly,
+            Permission::DeleteAnyMsg | Permission::Invite | Permission::RemoveMember | Permission::Rename => {
+                matches!(self, RoomRole::Owner | RoomRole::Admin)
+            }
+            Permission::SetRole | Permission::Delete => self == RoomRole::Owner,
+        }
+    }
+}
+
+/// 命令需要的房间权限，不针对某个房间的命令返回 None
+pub fn required(cmd: &ClientCommand) -> Option<(i32, Permission)> {
+    match cmd {
+        ClientCommand::RoomMsgs(req) => Some((req.room_id, Permission::ReadHistory)),
+        ClientCommand::SendMsg(req) => Some((req.room_id, Permission::SendMsg)),
+        ClientCommand::RemoveMember(req) => Some((req.room_id, Permission::RemoveMember)),
+        ClientCommand::RenameRoom(req) => Some((req.room_id, Permission::Rename)),
+        ClientCommand::SetMemberRole(req) => Some((req.room_id, Permission::SetRole)),
+        ClientCommand::DeleteRoom(req) => Some((req.room_id, Permission::Delete)),
+        ClientCommand::InviteUser(req) => Some((req.room_id, Permission::Invite)),
+        ClientCommand::CreateInviteCode(req) => Some((req.room_id, Permission::Invite)),
+        ClientCommand::MarkRead(req) => Some((req.room_id, Permission::ReadHistory)),
+        ClientCommand::Typing(req) => Some((req.room_id, Permission::SendMsg)),
+        ClientCommand::StopTyping(req) => Some((req.room_id, Permission::ReadHistory)),
+        ClientCommand::CreatePoll(req) => Some((req.room_id, Permission::SendMsg)),
+        ClientCommand::Rooms
+        | ClientCommand::CreateRoom(_)
+        | ClientCommand::Enter(_)
+        | ClientCommand::LeaveRoom(_)
+        | ClientCommand::AcceptInvite(_)
+        | ClientCommand::DeclineInvite(_)
+        | ClientCommand::Invites
+        | ClientCommand::OpenDirect(_)
+        | ClientCommand::MyMentions(_)
+        | ClientCommand::ReadMentions(_)
+        | ClientCommand::SetStatus(_)
+        | ClientCommand::Presence(_)
+        | ClientCommand::Ping
+        | ClientCommand::Pong
+        | ClientCommand::Sync(_)
+        // 搜索只查自己所在的房间
+        | ClientCommand::SearchMsgs(_)
+        // 消息和投票相关的权限要先查出所在房间，在处理函数里检查
+        | ClientCommand::EditMsg(_)
+        | ClientCommand::DeleteMsg(_)
+        | ClientCommand::ThreadMsgs(_)
+        | ClientCommand::AddReaction(_)
+        | ClientCommand::RemoveReaction(_)
+        | ClientCommand::Vote(_)
+        | ClientCommand::ClosePoll(_) => None,
+    }
+}
+
+/// 检查用户对房间是否有某项权限，返回房间和用户的成员记录
+pub async fn check_room(state: &ChatState, room_id: i32, user_id: u64, perm: Permission) -> Result<(Room, Option<RoomMember>)> {
+    let room = get_room(&state.pool, room_id).await.ok_or_else(|| ErrorCode::RoomNotFound.error("room not found"))?;
+    let member = get_room_member(&state.pool, room_id, user_id).await;
+    match &member {
+        Some(member) if member.role().can(perm) => {}
+        Some(_) => return Err(ErrorCode::Forbidden.error(format!("no permission to {:?} in this room", perm))),
+        // 公共房间不加入也可以看历史消息
+        None if perm == Permission::ReadHistory && room.room_type == RoomType::Public as i32 => {}
+        None => return Err(ErrorCode::NotRoomMember.error("not a member of this room")),
+    }
+    Ok((room, member))
+}
+
+pub async fn check(state: &ChatState, user_id: u64, cmd: &ClientCommand) -> Result<()> {
+    if let Some((room_id, perm)) = required(cmd) {
+        check_room(state, room_id, user_id, perm).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_role_can() {
+        assert!(RoomRole::Owner.can(Permission::Delete));
+        assert!(!RoomRole::Admin.can(Permission::Delete));
+        assert!(RoomRole::Admin.can(Permission::Rename));
+        assert!(!RoomRole::Member.can(Permission::Invite));
+        assert!(!RoomRole::Member.can(Permission::DeleteAnyMsg));
+        assert!(RoomRole::Member.can(Permission::SendMsg));
+        assert!(!RoomRole::ReadOnly.can(Permission::SendMsg));
+        assert!(RoomRole::ReadOnly.can(Permission::ReadHistory));
+    }
+}
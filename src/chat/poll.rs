@@ -0,0 +1,199 @@
+//! 投票
+//!
+//! 投票以一条 msg_type 为 Poll 的消息出现在房间历史里，选项和票数保存在 polls 相关的表里。
+//! 投票、关闭后向房间成员推送 `PollUpdated`，设置了截止时间的投票到时间后自动关闭。
+
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use anyhow::Result;
+use log::error;
+use sqlx::types::chrono::{Local, NaiveDateTime};
+
+use crate::{dao::{chatmsg_dao::get_chat_msg_by_id, poll_dao::{self, close_poll, count_voters, count_votes, get_open_polls_with_deadline, get_poll, get_poll_options, get_polls_by_msgs, set_votes, NewPoll}, room_dao::{get_room_member_ids, update_last_read}}, models::{chatmsg::SYSTEM_SENDER, poll::Poll}, web::common::ErrorCode};
+
+use super::{chatcmd::{broadcast_room, send_to, CmdCtx}, chatserver::ChatState, permission::{check_room, Permission}, protocol::{PollInfo, PollOptionInfo, ReqClosePoll, ReqCreatePoll, ReqVote, SentMsg, ServerEvent}};
+
+const MAX_POLL_OPTIONS: usize = 10;
+const MAX_QUESTION_CHARS: usize = 500;
+const MAX_OPTION_CHARS: usize = 200;
+
+/// 组装投票信息，key 为投票所在的消息 id，viewer 为查看投票的用户
+pub(crate) async fn poll_infos(state: &ChatState, viewer: u64, polls: Vec<Poll>) -> Result<HashMap<i32, PollInfo>> {
+    let poll_ids: Vec<i32> = polls.iter().map(|poll| poll.id).collect();
+    let votes: HashMap<i32, (i64, bool)> = count_votes(&state.pool, &poll_ids, viewer).await?.into_iter()
+        .map(|(option_id, count, me)| (option_id, (count, me)))
+        .collect();
+    let voters: HashMap<i32, i64> = count_voters(&state.pool, &poll_ids).await?.into_iter().collect();
+    let mut options: HashMap<i32, Vec<PollOptionInfo>> = HashMap::new();
+    let mut my_votes: HashMap<i32, Vec<i32>> = HashMap::new();
+    for option in get_poll_options(&state.pool, &poll_ids).await? {
+        let (count, me) = votes.get(&option.id).copied().unwrap_or_default();
+        if me {
+            my_votes.entry(option.poll_id).or_default().push(option.id);
+        }
+        options.entry(option.poll_id).or_default().push(PollOptionInfo { id: option.id, text: option.text, votes: count });
+    }
+    let now = Local::now().naive_local();
+    Ok(polls.into_iter().map(|poll| {
+        let info = PollInfo {
+            id: poll.id,
+            msg_id: poll.msg_id,
+            room_id: poll.room_id,
+            creator: poll.creator,
+            closed: poll.is_closed(now),
+            question: poll.question,
+            multi_choice: poll.multi_choice,
+            closes_at: poll.closes_at.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+            options: options.remove(&poll.id).unwrap_or_default(),
+            voters: voters.get(&poll.id).copied().unwrap_or(0),
+            my_votes: my_votes.remove(&poll.id).unwrap_or_default(),
+        };
+        (poll.msg_id, info)
+    }).collect())
+}
+
+/// 消息列表里的投票，key 为消息 id
+pub(crate) async fn msg_polls(state: &ChatState, viewer: u64, msg_ids: &[i32]) -> Result<HashMap<i32, PollInfo>> {
+    let polls = get_polls_by_msgs(&state.pool, msg_ids).await?;
+    poll_infos(state, viewer, polls).await
+}
+
+async fn poll_info(state: &ChatState, viewer: u64, poll: Poll) -> Result<PollInfo> {
+    poll_infos(state, viewer, vec![poll]).await?.into_values().next()
+        .ok_or_else(|| ErrorCode::PollNotFound.error("poll not found"))
+}
+
+/// 截止时间格式为 `2025-03-01 08:00:00`，必须晚于当前时间
+fn parse_closes_at(time: &str) -> Result<NaiveDateTime> {
+    let closes_at = NaiveDateTime::parse_from_str(time.trim(), "%Y-%m-%d %H:%M:%S")
+        .map_err(|_| ErrorCode::BadRequest.error(format!("invalid closes_at:{}", time)))?;
+    if closes_at <= Local::now().naive_local() {
+        return Err(ErrorCode::BadRequest.error("closes_at is in the past"));
+    }
+    Ok(closes_at)
+}
+
+pub async fn create_poll(state: Arc<ChatState>, req: ReqCreatePoll, ctx: &CmdCtx<'_>) -> Result<()> {
+    let question = req.question.trim();
+    if question.is_empty() || question.chars().count() > MAX_QUESTION_CHARS {
+        return Err(ErrorCode::BadRequest.error(format!("question must be 1 to {} characters", MAX_QUESTION_CHARS)));
+    }
+    let options: Vec<String> = req.options.iter().map(|option| option.trim().to_string()).collect();
+    if options.len() < 2 || options.len() > MAX_POLL_OPTIONS {
+        return Err(ErrorCode::BadRequest.error(format!("a poll needs 2 to {} options", MAX_POLL_OPTIONS)));
+    }
+    if options.iter().any(|option| option.is_empty() || option.chars().count() > MAX_OPTION_CHARS) {
+        return Err(ErrorCode::BadRequest.error(format!("option must be 1 to {} characters", MAX_OPTION_CHARS)));
+    }
+    if options.iter().collect::<HashSet<_>>().len() != options.len() {
+        return Err(ErrorCode::BadRequest.error("duplicate options"));
+    }
+    let closes_at = req.closes_at.as_deref().map(parse_closes_at).transpose()?;
+    let poll = poll_dao::create_poll(&state.pool, &NewPoll {
+        room_id: req.room_id,
+        creator: ctx.user.id,
+        question,
+        options: &options,
+        multi_choice: req.multi_choice,
+        closes_at,
+    }).await?;
+    let msg = get_chat_msg_by_id(&state.pool, poll.msg_id).await?;
+    // 自己发的消息视为已读
+    update_last_read(&state.pool, poll.room_id, ctx.user.id, msg.id).await?;
+    schedule_close(state.clone(), poll.clone());
+    let room_id = poll.room_id;
+    let info = poll_info(&state, ctx.user.id, poll).await?;
+    let sent = SentMsg { msg, attachments: vec![], poll: Some(info) };
+    ctx.reply_and_broadcast(&state, room_id, &ServerEvent::RspSendMsg(sent)).await
+}
+
+pub async fn vote(state: Arc<ChatState>, req: ReqVote, ctx: &CmdCtx<'_>) -> Result<()> {
+    let poll = get_poll(&state.pool, req.poll_id).await.ok_or_else(|| ErrorCode::PollNotFound.error("poll not found"))?;
+    check_room(&state, poll.room_id, ctx.user.id, Permission::SendMsg).await?;
+    let msg = get_chat_msg_by_id(&state.pool, poll.msg_id).await?;
+    if msg.deleted {
+        return Err(ErrorCode::PollNotFound.error("poll has been deleted"));
+    }
+    if poll.is_closed(Local::now().naive_local()) {
+        return Err(ErrorCode::PollClosed.error("poll is closed"));
+    }
+    let mut option_ids = req.option_ids;
+    option_ids.sort_unstable();
+    option_ids.dedup();
+    if !poll.multi_choice && option_ids.len() > 1 {
+        return Err(ErrorCode::BadRequest.error("this poll allows only one choice"));
+    }
+    let valid: HashSet<i32> = get_poll_options(&state.pool, &[poll.id]).await?.into_iter().map(|option| option.id).collect();
+    if option_ids.iter().any(|id| !valid.contains(id)) {
+        return Err(ErrorCode::BadRequest.error("option is not in this poll"));
+    }
+    set_votes(&state.pool, &poll, ctx.user.id, &option_ids).await?;
+    reply_poll(&state, ctx, poll).await
+}
+
+pub async fn close(state: Arc<ChatState>, req: ReqClosePoll, ctx: &CmdCtx<'_>) -> Result<()> {
+    let mut poll = get_poll(&state.pool, req.poll_id).await.ok_or_else(|| ErrorCode::PollNotFound.error("poll not found"))?;
+    // 发起人可以关闭自己的投票，房间管理员可以关闭任何投票
+    let perm = if poll.creator == ctx.user.id { Permission::SendMsg } else { Permission::DeleteAnyMsg };
+    check_room(&state, poll.room_id, ctx.user.id, perm).await?;
+    if !close_poll(&state.pool, &poll).await? {
+        return Err(ErrorCode::PollClosed.error("poll is already closed"));
+    }
+    poll.closed = true;
+    reply_poll(&state, ctx, poll).await
+}
+
+/// 把最新的票数回复给操作者，其他成员收到的 my_votes 为空
+async fn reply_poll(state: &ChatState, ctx: &CmdCtx<'_>, poll: Poll) -> Result<()> {
+    let room_id = poll.room_id;
+    let mut info = poll_info(state, ctx.user.id, poll).await?;
+    let rsp = ServerEvent::PollUpdated(info.clone());
+    ctx.reply(state, &rsp).await?;
+    ctx.push_other_conns(state, &rsp).await?;
+    info.my_votes.clear();
+    let rsp = ServerEvent::PollUpdated(info);
+    for member in get_room_member_ids(&state.pool, room_id).await? {
+        if member != ctx.user.id {
+            send_to(state, member, &rsp).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 到截止时间后关闭投票并推送给房间成员，没有截止时间的什么也不做
+pub fn schedule_close(state: Arc<ChatState>, poll: Poll) {
+    let Some(closes_at) = poll.closes_at else {
+        return;
+    };
+    tokio::spawn(async move {
+        let wait = (closes_at - Local::now().naive_local()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        if let Err(e) = expire(&state, poll).await {
+            error!("close poll error:{}", e);
+        }
+    });
+}
+
+async fn expire(state: &ChatState, mut poll: Poll) -> Result<()> {
+    // 投票消息已经撤回或随房间删除的不再关闭和推送
+    let deleted = get_chat_msg_by_id(&state.pool, poll.msg_id).await.map(|msg| msg.deleted).unwrap_or(true);
+    if deleted {
+        return Ok(());
+    }
+    // 已经被手动关闭的不再推送
+    if !close_poll(&state.pool, &poll).await? {
+        return Ok(());
+    }
+    poll.closed = true;
+    let room_id = poll.room_id;
+    let info = poll_info(state, SYSTEM_SENDER, poll).await?;
+    broadcast_room(state, room_id, &ServerEvent::PollUpdated(info)).await
+}
+
+/// 服务启动时重新安排还没关闭的投票，已经过期的会马上关闭
+pub async fn schedule_pending(state: Arc<ChatState>) -> Result<()> {
+    for poll in get_open_polls_with_deadline(&state.pool).await? {
+        schedule_close(state.clone(), poll);
+    }
+    Ok(())
+}
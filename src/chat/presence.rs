@@ -0,0 +1,91 @@
+//! 在线状态
+//!
+//! 用户的第一个连接建立时变为在线，最后一个连接断开时变为离线并记录最后在线时间；
+//! 在线期间客户端可以设置为离开并附带自定义状态文字。状态变化推送给和该用户共享房间的用户。
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+
+use crate::{dao::{room_dao::get_room_peers, user_dao::{get_presences, update_last_seen, update_status}}, models::{presence::{PresenceStatus, UserPresence}, user::User}, web::common::ErrorCode};
+
+use super::{chatcmd::{send_to, CmdCtx}, chatserver::ChatState, protocol::{PresenceInfo, ReqPresence, ReqSetStatus, RspPresence, ServerEvent}};
+
+/// 一次最多查询的用户数
+const MAX_PRESENCE_QUERY: usize = 200;
+const MAX_STATUS_TEXT: usize = 100;
+
+fn presence_info(presence: UserPresence, online: bool) -> PresenceInfo {
+    // 没有连接时一律离线，客户端设置的状态只在在线期间生效
+    let status = match PresenceStatus::from_i32(presence.status) {
+        _ if !online => PresenceStatus::Offline,
+        Some(PresenceStatus::Away) => PresenceStatus::Away,
+        _ => PresenceStatus::Online,
+    };
+    PresenceInfo {
+        user_id: presence.id,
+        status: status as i32,
+        status_text: presence.status_text,
+        last_seen_at: presence.last_seen_at.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()),
+    }
+}
+
+async fn is_online(state: &ChatState, user_id: u64) -> bool {
+    state.conn_map.read().await.contains_key(&user_id)
+}
+
+/// 把用户当前的状态推送给和他共享房间的在线用户
+async fn broadcast_presence(state: &ChatState, user_id: u64) -> Result<PresenceInfo> {
+    let presence = get_presences(&state.pool, &[user_id]).await?.pop()
+        .ok_or_else(|| ErrorCode::UserNotFound.error("user not found"))?;
+    let info = presence_info(presence, is_online(state, user_id).await);
+    let event = ServerEvent::PresenceChanged(info.clone());
+    for peer in get_room_peers(&state.pool, user_id).await? {
+        send_to(state, peer, &event).await?;
+    }
+    Ok(info)
+}
+
+/// 用户的第一个连接建立后调用
+pub(crate) async fn went_online(state: &ChatState, user: &User) -> Result<()> {
+    broadcast_presence(state, user.id).await.map(|_| ())
+}
+
+/// 用户的最后一个连接断开后调用
+pub(crate) async fn went_offline(state: &ChatState, user: &User) -> Result<()> {
+    update_last_seen(&state.pool, user.id).await?;
+    broadcast_presence(state, user.id).await.map(|_| ())
+}
+
+pub async fn set_status(state: Arc<ChatState>, req: ReqSetStatus, ctx: &CmdCtx<'_>) -> Result<()> {
+    let status = match PresenceStatus::from_i32(req.status) {
+        Some(status @ (PresenceStatus::Online | PresenceStatus::Away)) => status,
+        _ => return Err(ErrorCode::BadRequest.error(format!("invalid status:{}", req.status))),
+    };
+    let status_text = req.status_text.as_deref().map(str::trim).filter(|text| !text.is_empty());
+    if status_text.is_some_and(|text| text.chars().count() > MAX_STATUS_TEXT) {
+        return Err(ErrorCode::BadRequest.error("status text is too long"));
+    }
+    update_status(&state.pool, ctx.user.id, status as i32, status_text).await?;
+    let info = broadcast_presence(&state, ctx.user.id).await?;
+    let rsp = ServerEvent::PresenceChanged(info);
+    ctx.reply(&state, &rsp).await?;
+    ctx.push_other_conns(&state, &rsp).await
+}
+
+pub async fn presence(state: Arc<ChatState>, req: ReqPresence, ctx: &CmdCtx<'_>) -> Result<()> {
+    if req.user_ids.len() > MAX_PRESENCE_QUERY {
+        return Err(ErrorCode::BadRequest.error(format!("at most {} users per query", MAX_PRESENCE_QUERY)));
+    }
+    let mut presences: HashMap<u64, UserPresence> = get_presences(&state.pool, &req.user_ids).await?.into_iter()
+        .map(|presence| (presence.id, presence))
+        .collect();
+    let online: Vec<bool> = {
+        let conn_map = state.conn_map.read().await;
+        req.user_ids.iter().map(|id| conn_map.contains_key(id)).collect()
+    };
+    let users = req.user_ids.iter().zip(online)
+        .filter_map(|(id, online)| presences.remove(id).map(|presence| presence_info(presence, online)))
+        .collect();
+    ctx.reply(&state, &ServerEvent::RspPresence(RspPresence { users })).await
+}
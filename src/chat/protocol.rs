@@ -0,0 +1,836 @@
+//! 聊天协议定义
+//!
+//! 客户端发来的帧统一解码为 [`ClientFrame`]，服务端推送的帧统一由 [`ServerEvent`] 编码。
+//! 新协议（v2）中 `data` 是 JSON 对象；旧客户端发送的 `{cmd, data: "<json string>"}`
+//! 会在解码时由兼容层展开，迁移期间可以继续使用。连接上收到旧格式的帧之后，
+//! 发给这个连接的帧也由 [`to_legacy`] 转回旧格式。
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{models::{chatmsg::ChatMessage, room::Room}, web::common::ErrorCode};
+
+/// 当前协议版本，服务端发出的每一帧都会带上
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// 旧版协议帧，`data` 是转义后的 JSON 字符串
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatCammand {
+    pub cmd: String,
+    pub data: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClientFrame {
+    #[serde(default)]
+    pub v: Option<u32>,
+    /// 客户端自定义的请求 id，会原样带回直接响应
+    #[serde(default)]
+    pub req_id: Option<String>,
+    #[serde(flatten)]
+    pub cmd: ClientCommand,
+    /// 帧是否是旧格式，没有 data 又没有版本号的帧看不出来，为 None
+    #[serde(skip)]
+    pub legacy: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "data")]
+pub enum ClientCommand {
+    Rooms,
+    CreateRoom(ReqCreateRoom),
+    Enter(ReqEnter),
+    RoomMsgs(ReqRoomMsgs),
+    SendMsg(ReqSendMsg),
+    LeaveRoom(ReqLeaveRoom),
+    RemoveMember(ReqRemoveMember),
+    RenameRoom(ReqRenameRoom),
+    SetMemberRole(ReqSetMemberRole),
+    DeleteRoom(ReqDeleteRoom),
+    InviteUser(ReqInviteUser),
+    CreateInviteCode(ReqCreateInviteCode),
+    AcceptInvite(ReqAcceptInvite),
+    DeclineInvite(ReqDeclineInvite),
+    Invites,
+    OpenDirect(ReqOpenDirect),
+    EditMsg(ReqEditMsg),
+    DeleteMsg(ReqDeleteMsg),
+    ThreadMsgs(ReqThreadMsgs),
+    AddReaction(ReqReaction),
+    RemoveReaction(ReqReaction),
+    MyMentions(ReqMyMentions),
+    ReadMentions(ReqReadMentions),
+    MarkRead(ReqMarkRead),
+    Typing(ReqTyping),
+    StopTyping(ReqTyping),
+    SetStatus(ReqSetStatus),
+    Presence(ReqPresence),
+    /// 客户端探测服务端，回复 Pong
+    Ping,
+    /// 回应服务端的 Ping
+    Pong,
+    Sync(ReqSync),
+    SearchMsgs(ReqSearchMsgs),
+    CreatePoll(ReqCreatePoll),
+    Vote(ReqVote),
+    ClosePoll(ReqClosePoll),
+}
+
+impl ClientCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientCommand::Rooms => "Rooms",
+            ClientCommand::CreateRoom(_) => "CreateRoom",
+            ClientCommand::Enter(_) => "Enter",
+            ClientCommand::RoomMsgs(_) => "RoomMsgs",
+            ClientCommand::SendMsg(_) => "SendMsg",
+            ClientCommand::LeaveRoom(_) => "LeaveRoom",
+            ClientCommand::RemoveMember(_) => "RemoveMember",
+            ClientCommand::RenameRoom(_) => "RenameRoom",
+            ClientCommand::SetMemberRole(_) => "SetMemberRole",
+            ClientCommand::DeleteRoom(_) => "DeleteRoom",
+            ClientCommand::InviteUser(_) => "InviteUser",
+            ClientCommand::CreateInviteCode(_) => "CreateInviteCode",
+            ClientCommand::AcceptInvite(_) => "AcceptInvite",
+            ClientCommand::DeclineInvite(_) => "DeclineInvite",
+            ClientCommand::Invites => "Invites",
+            ClientCommand::OpenDirect(_) => "OpenDirect",
+            ClientCommand::EditMsg(_) => "EditMsg",
+            ClientCommand::DeleteMsg(_) => "DeleteMsg",
+            ClientCommand::ThreadMsgs(_) => "ThreadMsgs",
+            ClientCommand::AddReaction(_) => "AddReaction",
+            ClientCommand::RemoveReaction(_) => "RemoveReaction",
+            ClientCommand::MyMentions(_) => "MyMentions",
+            ClientCommand::ReadMentions(_) => "ReadMentions",
+            ClientCommand::MarkRead(_) => "MarkRead",
+            ClientCommand::Typing(_) => "Typing",
+            ClientCommand::StopTyping(_) => "StopTyping",
+            ClientCommand::SetStatus(_) => "SetStatus",
+            ClientCommand::Presence(_) => "Presence",
+            ClientCommand::Ping => "Ping",
+            ClientCommand::Pong => "Pong",
+            ClientCommand::Sync(_) => "Sync",
+            ClientCommand::SearchMsgs(_) => "SearchMsgs",
+            ClientCommand::CreatePoll(_) => "CreatePoll",
+            ClientCommand::Vote(_) => "Vote",
+            ClientCommand::ClosePoll(_) => "ClosePoll",
+        }
+    }
+}
+
+/// 区分对某个请求的直接响应和服务端主动推送
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameKind {
+    Reply,
+    Push,
+}
+
+#[derive(Debug, Serialize)]
+struct ServerFrame<'a> {
+    v: u32,
+    kind: FrameKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    req_id: Option<&'a str>,
+    #[serde(flatten)]
+    event: &'a ServerEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "cmd", content = "data")]
+pub enum ServerEvent {
+    RspRooms(RoomInfo),
+    RspRoomMsgs(RspRoomMsgs),
+    RspSendMsg(SentMsg),
+    Error(RspError),
+    /// 推送给被邀请人
+    Invite(InviteInfo),
+    RspInvite(InviteInfo),
+    RspInvites(RspInvites),
+    RspOpenDirect(RoomItem),
+    MsgEdited(ChatMessage),
+    MsgDeleted(RspMsgDeleted),
+    RspThreadMsgs(RspThreadMsgs),
+    ReactionChanged(RspReactionChanged),
+    /// 推送给被提及的用户，不论是否正在查看该房间
+    Mentioned(MentionInfo),
+    RspMentions(RspMentions),
+    RspMarkRead(RspMarkRead),
+    /// 小房间里推送给其他成员，告知某人已读到哪条消息
+    ReadReceipt(RspReadReceipt),
+    /// 推送给房间里的其他在线成员，typing 为 false 表示停止输入
+    TypingChanged(RspTyping),
+    /// 推送给和该用户共享房间的用户
+    PresenceChanged(PresenceInfo),
+    RspPresence(RspPresence),
+    /// 服务端发起的心跳，客户端应回复 Pong
+    Ping,
+    Pong,
+    RspSync(RspSync),
+    RspSearchMsgs(RspSearchMsgs),
+    /// 投票、关闭投票后推送给房间成员，其他成员收到的 my_votes 为空
+    PollUpdated(PollInfo),
+}
+
+impl ServerEvent {
+    pub fn encode(&self, kind: FrameKind, req_id: Option<&str>) -> Result<String> {
+        Ok(serde_json::to_string(&ServerFrame { v: PROTOCOL_VERSION, kind, req_id, event: self })?)
+    }
+}
+
+/// 命令处理失败时回给客户端，`code` 取自 [`ErrorCode`]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspError {
+    pub code: i32,
+    pub message: String,
+    pub cmd: Option<String>,
+}
+
+impl RspError {
+    pub fn new(e: &anyhow::Error, cmd: Option<String>) -> Self {
+        Self {
+            code: ErrorCode::of(e).code(),
+            message: ErrorCode::client_message(e),
+            cmd,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub rooms: Vec<RoomItem>,
+}
+
+/// 房间列表中的一项，私聊房间的 display_name 是对方的用户名
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomItem {
+    #[serde(flatten)]
+    pub room: Room,
+    pub display_name: String,
+    /// 私聊房间的对方
+    pub peer_id: Option<u64>,
+    /// 未读消息数，不是成员的公共房间为 0
+    pub unread: i64,
+    /// 最后一条消息，用于列表预览
+    pub last_msg: Option<ClientChatMsg>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqCreateRoom {
+    pub room_type: i32,
+    pub room_name: String,
+    pub members: Vec<u64>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqEnter {
+    pub room_id: i32
+}
+
+/// before、after、around 最多传一个，都不传时返回最新的消息
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqRoomMsgs {
+    pub room_id: i32,
+    /// 旧客户端使用的向前翻页参数，等同于 before
+    #[serde(default)]
+    pub last_id: Option<i32>,
+    /// id 小于 before 的消息
+    #[serde(default)]
+    pub before: Option<i32>,
+    /// id 大于 after 的消息
+    #[serde(default)]
+    pub after: Option<i32>,
+    /// 以这条消息为中心，前后各取一半，用于跳转到某条消息
+    #[serde(default)]
+    pub around: Option<i32>,
+    /// 每页条数，默认 20，最多 100
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspRoomMsgs {
+    pub room_id: i32,
+    /// 按 id 升序
+    pub msgs: Vec<ClientChatMsg>,
+    /// 翻页方向上是否还有消息：after 为更新的消息，其他为更早的消息
+    pub has_more: bool,
+    /// 只有 around 时返回，之后是否还有更新的消息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_more_after: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientChatMsg {
+    pub msg: ChatMessage,
+    pub user_name: String,
+    /// 以这条消息为根的话题里的回复数
+    pub reply_count: i64,
+    pub reactions: Vec<ReactionCount>,
+    pub attachments: Vec<AttachmentInfo>,
+    /// 投票消息的选项和票数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollInfo>,
+}
+
+/// 新消息推送，在消息字段之外带上附件，没有附件的消息和旧格式一致
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SentMsg {
+    #[serde(flatten)]
+    pub msg: ChatMessage,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<AttachmentInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentInfo {
+    pub id: i32,
+    pub name: String,
+    pub size: i64,
+    pub mime: String,
+    /// 下载地址，需要带上登录 token
+    pub url: String,
+    /// 图片的尺寸
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// 图片缩略图，后台生成完成前为空
+    pub thumbnail_url: Option<String>,
+    pub thumb_width: Option<i32>,
+    pub thumb_height: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: i64,
+    /// 当前用户是否回应过
+    pub me: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqSendMsg {
+    pub room_id: i32,
+    /// 卡片消息为卡片的 JSON，见 [`MsgCard`]
+    pub msg: String,
+    /// 取值见 `MsgType`，默认纯文本，不能发送系统通知
+    #[serde(default)]
+    pub msg_type: Option<i32>,
+    /// 回复某条消息
+    #[serde(default)]
+    pub reply_to: Option<i32>,
+    /// 先通过 /upload 上传得到的附件 id
+    #[serde(default)]
+    pub attachments: Vec<i32>,
+}
+
+/// 卡片消息，序列化后存在消息内容里
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MsgCard {
+    pub title: String,
+    /// markdown 格式的正文
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<CardField>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<CardButton>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardField {
+    pub name: String,
+    pub value: String,
+}
+
+/// 卡片按钮，url 为点击后打开的链接，没有 url 时客户端把 action 原样回传给发送方
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CardButton {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+}
+
+/// 尽量取出原始帧里的字符串字段（cmd、req_id），解码失败时用于回报错误
+pub fn peek_str(text: &str, key: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(text).ok()?;
+    value.get(key)?.as_str().map(|v| v.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqLeaveRoom {
+    pub room_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqRemoveMember {
+    pub room_id: i32,
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqRenameRoom {
+    pub room_id: i32,
+    pub room_name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqSetMemberRole {
+    pub room_id: i32,
+    pub user_id: u64,
+    /// 取值见 `RoomRole`，不能设置为 owner
+    pub role: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqDeleteRoom {
+    pub room_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqInviteUser {
+    pub room_id: i32,
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqCreateInviteCode {
+    pub room_id: i32,
+    /// 有效秒数，不传表示不过期
+    pub expires_in: Option<u64>,
+}
+
+/// invite_id 和 code 二选一
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqAcceptInvite {
+    pub invite_id: Option<i32>,
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqDeclineInvite {
+    pub invite_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InviteInfo {
+    pub id: i32,
+    pub room_id: i32,
+    pub room_name: String,
+    pub inviter: u64,
+    pub inviter_name: String,
+    pub code: Option<String>,
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspInvites {
+    pub invites: Vec<InviteInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqOpenDirect {
+    pub user_id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqEditMsg {
+    pub msg_id: i32,
+    pub msg: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqDeleteMsg {
+    pub msg_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspMsgDeleted {
+    pub room_id: i32,
+    pub msg_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqThreadMsgs {
+    /// 话题的根消息，传入话题里的回复时按它所在的话题处理
+    pub root_id: i32,
+    /// 旧客户端使用的向前翻页参数，等同于 before
+    #[serde(default)]
+    pub last_id: Option<i32>,
+    /// id 小于 before 的回复
+    #[serde(default)]
+    pub before: Option<i32>,
+    /// 每页条数，默认 20，最多 100
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspThreadMsgs {
+    pub root: ClientChatMsg,
+    /// 按 id 升序
+    pub msgs: Vec<ClientChatMsg>,
+    /// 是否还有更早的回复
+    pub has_more: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqReaction {
+    pub msg_id: i32,
+    pub emoji: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspReactionChanged {
+    pub room_id: i32,
+    pub msg_id: i32,
+    pub emoji: String,
+    pub user_id: u64,
+    pub added: bool,
+    /// 变化后该表情的总数
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqMyMentions {
+    pub last_id: Option<i32>,
+}
+
+/// room_id 为空时把所有提及标记为已读
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqReadMentions {
+    pub room_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MentionInfo {
+    pub id: i32,
+    pub room_id: i32,
+    pub room_name: String,
+    /// 取值见 `MentionKind`
+    pub kind: i32,
+    pub msg: ClientChatMsg,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspMentions {
+    pub mentions: Vec<MentionInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqMarkRead {
+    pub room_id: i32,
+    /// 已读到的最后一条消息
+    pub msg_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspMarkRead {
+    pub room_id: i32,
+    /// 更新后的已读指针
+    pub msg_id: i32,
+    pub unread: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspReadReceipt {
+    pub room_id: i32,
+    pub user_id: u64,
+    pub msg_id: i32,
+}
+
+/// Typing 和 StopTyping 都不回复
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqTyping {
+    pub room_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspTyping {
+    pub room_id: i32,
+    pub user_id: u64,
+    pub user_name: String,
+    pub typing: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqSetStatus {
+    /// 取值见 `PresenceStatus`，只能设置为在线或离开
+    pub status: i32,
+    pub status_text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqPresence {
+    pub user_ids: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceInfo {
+    pub user_id: u64,
+    /// 取值见 `PresenceStatus`
+    pub status: i32,
+    pub status_text: Option<String>,
+    pub last_seen_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspPresence {
+    pub users: Vec<PresenceInfo>,
+}
+
+/// since 为上次同步返回的游标，不传时只返回当前游标
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqSync {
+    pub since: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemberChange {
+    pub room_id: i32,
+    pub user_id: u64,
+    /// false 表示离开或被移出
+    pub joined: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspSync {
+    /// 下次同步时带上的游标
+    pub cursor: i64,
+    pub has_more: bool,
+    /// 新消息和被编辑过的消息，按 id 升序，都是当前的内容
+    pub msgs: Vec<ClientChatMsg>,
+    pub deleted: Vec<RspMsgDeleted>,
+    pub members: Vec<MemberChange>,
+}
+
+/// 只搜索自己所在的房间，时间格式为 `2025-03-01` 或 `2025-03-01 08:00:00`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqSearchMsgs {
+    pub query: String,
+    #[serde(default)]
+    pub room_id: Option<i32>,
+    #[serde(default)]
+    pub sender: Option<u64>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    /// 上一页最后一条结果的消息 id
+    #[serde(default)]
+    pub last_id: Option<i32>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub room_id: i32,
+    pub room_name: String,
+    /// 命中的关键词用 `<em>` 标出，其余内容已做 HTML 转义
+    pub snippet: String,
+    pub msg: ClientChatMsg,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RspSearchMsgs {
+    /// 按消息 id 倒序
+    pub hits: Vec<SearchHit>,
+    pub has_more: bool,
+}
+
+/// 创建投票，closes_at 格式为 `2025-03-01 08:00:00`，到时间后自动关闭
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqCreatePoll {
+    pub room_id: i32,
+    pub question: String,
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub multi_choice: bool,
+    #[serde(default)]
+    pub closes_at: Option<String>,
+}
+
+/// 用新的选择替换之前的投票，option_ids 为空表示撤回
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqVote {
+    pub poll_id: i32,
+    pub option_ids: Vec<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReqClosePoll {
+    pub poll_id: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollInfo {
+    pub id: i32,
+    pub msg_id: i32,
+    pub room_id: i32,
+    pub creator: u64,
+    pub question: String,
+    pub multi_choice: bool,
+    pub closes_at: Option<String>,
+    pub closed: bool,
+    pub options: Vec<PollOptionInfo>,
+    /// 投过票的人数
+    pub voters: i64,
+    /// 查看者自己选择的选项
+    pub my_votes: Vec<i32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PollOptionInfo {
+    pub id: i32,
+    pub text: String,
+    pub votes: i64,
+}
+
+/// 解码客户端发来的一帧，未知命令或格式错误的 data 直接返回错误
+pub fn decode_client_frame(text: &str) -> Result<ClientFrame> {
+    decode_frame(text).map_err(|e| {
+        if ErrorCode::of(&e) != ErrorCode::BadRequest {
+            e
+        } else if e.to_string().starts_with("unknown variant") {
+            ErrorCode::UnknownCommand.error(e.to_string())
+        } else {
+            ErrorCode::BadRequest.error(e.to_string())
+        }
+    })
+}
+
+fn decode_frame(text: &str) -> Result<ClientFrame> {
+    let mut value: Value = serde_json::from_str(text)?;
+    let obj = value.as_object_mut().ok_or_else(|| ErrorCode::BadRequest.error("frame is not a json object"))?;
+    let legacy = match obj.get("data") {
+        Some(Value::String(_)) => Some(true),
+        Some(Value::Object(_)) => Some(false),
+        _ if obj.contains_key("v") => Some(false),
+        _ => None,
+    };
+    // 兼容旧协议：data 是 JSON 字符串时先展开
+    if let Some(Value::String(data)) = obj.get("data") {
+        let inner: Value = if data.trim().is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(data)?
+        };
+        obj.insert("data".to_string(), inner);
+    }
+    if obj.get("data").is_some_and(Value::is_null) {
+        obj.remove("data");
+    }
+    let mut frame: ClientFrame = match serde_json::from_value(value.clone()) {
+        Ok(frame) => frame,
+        // 无参命令允许携带 {}
+        Err(e) => match value.as_object_mut() {
+            Some(obj) if obj.get("data").is_some_and(|data| data.as_object().is_some_and(|m| m.is_empty())) => {
+                obj.remove("data");
+                serde_json::from_value(value).map_err(|_| e)?
+            }
+            _ => return Err(e.into()),
+        },
+    };
+    if let Some(v) = frame.v {
+        if v > PROTOCOL_VERSION {
+            return Err(ErrorCode::UnsupportedVersion.error(format!("unsupported protocol version:{}", v)));
+        }
+    }
+    frame.legacy = legacy;
+    Ok(frame)
+}
+
+/// 把编码好的帧转成旧格式：`data` 序列化成字符串，没有 data 的事件补一个空字符串
+pub fn to_legacy(frame: &str) -> Result<String> {
+    let mut value: Value = serde_json::from_str(frame)?;
+    if let Some(obj) = value.as_object_mut() {
+        let data = match obj.remove("data") {
+            Some(data) => data.to_string(),
+            None => String::new(),
+        };
+        obj.insert("data".to_string(), Value::String(data));
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_decode() -> anyhow::Result<()> {
+        let frame = decode_client_frame(r#"{"v":2,"cmd":"SendMsg","data":{"room_id":1,"msg":"hi"}}"#)?;
+        assert!(matches!(frame.cmd, ClientCommand::SendMsg(ReqSendMsg { room_id: 1, .. })));
+        assert_eq!(frame.req_id, None);
+        let frame = decode_client_frame(r#"{"req_id":"7","cmd":"RoomMsgs","data":{"room_id":1}}"#)?;
+        assert_eq!(frame.req_id.as_deref(), Some("7"));
+        let frame = decode_client_frame(r#"{"cmd":"Rooms"}"#)?;
+        assert!(matches!(frame.cmd, ClientCommand::Rooms));
+        let e = decode_client_frame(r#"{"cmd":"Unknown","data":{}}"#).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::UnknownCommand);
+        let e = decode_client_frame(r#"{"cmd":"Enter","data":{"room":1}}"#).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::BadRequest);
+        let e = decode_client_frame(r#"{"v":99,"cmd":"Rooms"}"#).unwrap_err();
+        assert_eq!(ErrorCode::of(&e), ErrorCode::UnsupportedVersion);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode() -> anyhow::Result<()> {
+        let event = ServerEvent::RspRooms(RoomInfo { rooms: vec![] });
+        let reply: Value = serde_json::from_str(&event.encode(FrameKind::Reply, Some("7"))?)?;
+        assert_eq!(reply["kind"], "reply");
+        assert_eq!(reply["req_id"], "7");
+        assert_eq!(reply["cmd"], "RspRooms");
+        let push: Value = serde_json::from_str(&event.encode(FrameKind::Push, None)?)?;
+        assert_eq!(push["kind"], "push");
+        assert!(push.get("req_id").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_legacy() -> anyhow::Result<()> {
+        let legacy = ChatCammand {
+            cmd: "Enter".to_string(),
+            data: r#"{"room_id":3}"#.to_string(),
+        };
+        let frame = decode_client_frame(&serde_json::to_string(&legacy)?)?;
+        assert!(matches!(frame.cmd, ClientCommand::Enter(ReqEnter { room_id: 3 })));
+        let frame = decode_client_frame(r#"{"cmd":"Rooms","data":""}"#)?;
+        assert!(matches!(frame.cmd, ClientCommand::Rooms));
+        let frame = decode_client_frame(r#"{"cmd":"Rooms","data":"{}"}"#)?;
+        assert!(matches!(frame.cmd, ClientCommand::Rooms));
+        assert_eq!(frame.legacy, Some(true));
+        assert_eq!(decode_client_frame(r#"{"v":2,"cmd":"Rooms"}"#)?.legacy, Some(false));
+        assert_eq!(decode_client_frame(r#"{"cmd":"Rooms"}"#)?.legacy, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_message() {
+        let e = ErrorCode::RoomNotFound.error("room not found");
+        assert_eq!(RspError::new(&e, None).message, "room not found");
+        let e: anyhow::Error = sqlx::Error::Protocol("Duplicate entry 'a' for key 'users.username'".to_string()).into();
+        let rsp = RspError::new(&e, None);
+        assert_eq!(rsp.code, ErrorCode::Database.code());
+        assert_eq!(rsp.message, "database error");
+        assert_eq!(RspError::new(&anyhow::anyhow!("SELECT * FROM users"), None).message, "internal error");
+    }
+
+    #[test]
+    fn test_to_legacy() -> anyhow::Result<()> {
+        let event = ServerEvent::RspRooms(RoomInfo { rooms: vec![] });
+        let legacy: ChatCammand = serde_json::from_str(&to_legacy(&event.encode(FrameKind::Push, None)?)?)?;
+        assert_eq!(legacy.cmd, "RspRooms");
+        assert_eq!(legacy.data, r#"{"rooms":[]}"#);
+        let legacy: ChatCammand = serde_json::from_str(&to_legacy(&ServerEvent::Ping.encode(FrameKind::Push, None)?)?)?;
+        assert_eq!(legacy.data, "");
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+//! 消息搜索
+//!
+//! 基于 chat_msgs.message 的 FULLTEXT 索引，只搜索调用者所在的房间，
+//! 返回带高亮的片段以及房间名、发送者名。聊天协议的 `SearchMsgs` 和 HTTP 接口共用这里的实现。
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Result;
+use sqlx::types::chrono::{NaiveDate, NaiveDateTime};
+
+use crate::{dao::{chatmsg_dao::{search_chat_msgs, MsgSearch}, room_dao::get_rooms_by_member}, web::common::ErrorCode};
+
+use super::{chatcmd::{client_msgs, display_names, CmdCtx}, chatserver::ChatState, protocol::{ReqSearchMsgs, RspSearchMsgs, SearchHit, ServerEvent}};
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 50;
+/// 片段最多保留的字符数
+const SNIPPET_CHARS: usize = 80;
+
+/// 把用户输入拆成关键词，去掉 BOOLEAN MODE 的运算符
+pub fn search_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = vec![];
+    for term in query.split_whitespace() {
+        let term: String = term.chars().filter(|c| !"+-<>()~*\"@".contains(*c)).collect();
+        if !term.is_empty() && !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// 每个关键词都必须出现，按短语匹配
+pub fn boolean_query(terms: &[String]) -> String {
+    terms.iter().map(|term| format!("+\"{}\"", term)).collect::<Vec<_>>().join(" ")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// 截取第一个命中附近的片段，命中的关键词用 `<em>` 包起来，其余内容做 HTML 转义
+pub fn highlight(message: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = message.chars().collect();
+    let terms: Vec<Vec<char>> = terms.iter().map(|term| term.chars().map(fold).collect()).collect();
+    // 每个字符是否落在某个命中里
+    let mut hit = vec![false; chars.len()];
+    for term in terms.iter().filter(|term| !term.is_empty()) {
+        for start in 0..chars.len().saturating_sub(term.len() - 1) {
+            if term.iter().enumerate().all(|(i, c)| fold(chars[start + i]) == *c) {
+                hit[start..start + term.len()].iter_mut().for_each(|h| *h = true);
+            }
+        }
+    }
+    let first = hit.iter().position(|h| *h).unwrap_or(0);
+    let start = first.saturating_sub(SNIPPET_CHARS / 4);
+    let end = (start + SNIPPET_CHARS).min(chars.len());
+    let start = end.saturating_sub(SNIPPET_CHARS).min(start);
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut i = start;
+    while i < end {
+        let in_hit = hit[i];
+        let mut j = i;
+        while j < end && hit[j] == in_hit {
+            j += 1;
+        }
+        let part = escape_html(&chars[i..j].iter().collect::<String>());
+        if in_hit {
+            snippet.push_str("<em>");
+            snippet.push_str(&part);
+            snippet.push_str("</em>");
+        } else {
+            snippet.push_str(&part);
+        }
+        i = j;
+    }
+    if end < chars.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+/// 时间条件支持 `2025-03-01` 和 `2025-03-01 08:00:00`，只有日期时 to 取当天结束
+fn normalize_time(time: &str, end_of_day: bool) -> Result<String> {
+    let time = time.trim();
+    if NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").is_ok() {
+        return Ok(time.to_string());
+    }
+    let date = NaiveDate::parse_from_str(time, "%Y-%m-%d")
+        .map_err(|_| ErrorCode::BadRequest.error(format!("invalid time:{}", time)))?;
+    Ok(format!("{} {}", date.format("%Y-%m-%d"), if end_of_day { "23:59:59" } else { "00:00:00" }))
+}
+
+/// 搜索 user_id 所在房间里的消息
+pub async fn search_msgs(state: &ChatState, user_id: u64, req: &ReqSearchMsgs) -> Result<RspSearchMsgs> {
+    let terms = search_terms(&req.query);
+    if terms.is_empty() {
+        return Err(ErrorCode::BadRequest.error("query is empty"));
+    }
+    let query = boolean_query(&terms);
+    let from = req.from.as_deref().map(|time| normalize_time(time, false)).transpose()?;
+    let to = req.to.as_deref().map(|time| normalize_time(time, true)).transpose()?;
+    let limit = req.limit.map_or(DEFAULT_PAGE_SIZE, |limit| limit as usize).clamp(1, MAX_PAGE_SIZE);
+    let search = MsgSearch {
+        query: &query,
+        room_id: req.room_id,
+        sender: req.sender,
+        from: from.as_deref(),
+        to: to.as_deref(),
+        last_id: req.last_id,
+        limit: limit as i64 + 1,
+    };
+    let mut msgs = search_chat_msgs(&state.pool, user_id, &search).await?;
+    let has_more = msgs.len() > limit;
+    msgs.truncate(limit);
+    let rooms = get_rooms_by_member(&state.pool, user_id).await?;
+    let names: HashMap<i32, String> = display_names(state, user_id, &rooms).await?.into_iter()
+        .map(|(room_id, (name, _))| (room_id, name))
+        .collect();
+    let hits = client_msgs(state, user_id, msgs).await?.into_iter().map(|msg| SearchHit {
+        room_id: msg.msg.room_id,
+        room_name: names.get(&msg.msg.room_id).cloned().unwrap_or_default(),
+        snippet: highlight(&msg.msg.message, &terms),
+        msg,
+    }).collect();
+    Ok(RspSearchMsgs { hits, has_more })
+}
+
+pub async fn search(state: Arc<ChatState>, req: ReqSearchMsgs, ctx: &CmdCtx<'_>) -> Result<()> {
+    let rsp = search_msgs(&state, ctx.user.id, &req).await?;
+    ctx.reply(&state, &ServerEvent::RspSearchMsgs(rsp)).await
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_search_terms() {
+        assert_eq!(search_terms(" rust  +tokio* rust \"\" "), vec!["rust", "tokio"]);
+        assert_eq!(boolean_query(&search_terms("开会 时间")), r#"+"开会" +"时间""#);
+        assert!(search_terms("+- ()").is_empty());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let terms = search_terms("rust");
+        assert_eq!(highlight("I like Rust & <b>go</b>", &terms), "I like <em>Rust</em> &amp; &lt;b&gt;go&lt;/b&gt;");
+        let long = format!("{}明天开会{}", "啊".repeat(100), "吧".repeat(100));
+        let snippet = highlight(&long, &search_terms("开会"));
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("<em>开会</em>"));
+        let before = snippet.split("<em>").next().unwrap_or_default();
+        assert_eq!(before.chars().count(), SNIPPET_CHARS / 4 + 1);
+    }
+}
@@ -0,0 +1,68 @@
+//! 离线同步
+//!
+//! 房间里的新消息、编辑、撤回和成员变化都记在 room_changes 里，自增 id 就是同步游标。
+//! 客户端重连后带上上次的游标发送 `Sync`，一次拿到离线期间的所有变化。
+//! 写变化的事务可能乱序提交，最近几秒的变化之前可能还有没提交的变化。
+//! 这些变化照常返回，但游标只前进到稳定的部分，下次同步会和这次有重叠而不会漏掉变化，
+//! 重复返回的消息客户端按消息 id 覆盖即可。
+
+use std::{collections::BTreeSet, sync::Arc};
+
+use anyhow::Result;
+
+use crate::{dao::{change_dao::{get_changes, latest_change_id}, chatmsg_dao::get_chat_msgs_by_ids}, models::change::ChangeKind};
+
+use super::{chatcmd::{client_msgs, CmdCtx}, chatserver::ChatState, protocol::{MemberChange, ReqSync, RspMsgDeleted, RspSync, ServerEvent}};
+
+/// 一次最多返回的变化条数，超过时 has_more 为 true，客户端用新游标继续同步
+const SYNC_LIMIT: usize = 500;
+/// 最近这么多秒内写入的变化可能还有更早的事务没提交，游标不越过它们
+const SYNC_HOLD_BACK_SECS: u64 = 5;
+
+pub async fn sync(state: Arc<ChatState>, req: ReqSync, ctx: &CmdCtx<'_>) -> Result<()> {
+    let user = ctx.user;
+    // 没有游标时只返回当前游标，客户端从这里开始同步
+    let Some(since) = req.since else {
+        let cursor = latest_change_id(&state.pool, SYNC_HOLD_BACK_SECS).await?;
+        let rsp = RspSync { cursor, has_more: false, msgs: vec![], deleted: vec![], members: vec![] };
+        return ctx.reply(&state, &ServerEvent::RspSync(rsp)).await;
+    };
+    // 先取稳定游标再取变化，取变化时新提交的只会落在游标之后
+    let settled = latest_change_id(&state.pool, SYNC_HOLD_BACK_SECS).await?;
+    let mut changes = get_changes(&state.pool, user.id, since, SYNC_LIMIT as i64 + 1).await?;
+    let has_more = changes.len() > SYNC_LIMIT;
+    changes.truncate(SYNC_LIMIT);
+    // 没取完时只能前进到这一批的最后一条
+    let reached = match changes.last() {
+        Some(change) if has_more => change.id,
+        _ => settled,
+    };
+    let cursor = reached.min(settled).max(since);
+    // 这一批全都还不稳定时游标前进不了，让客户端稍后再同步而不是马上重复拿同一批
+    let has_more = has_more && cursor > since;
+    // 同一条消息的多次变化只返回它现在的状态
+    let mut msg_ids = BTreeSet::new();
+    let mut members = vec![];
+    for change in &changes {
+        match (ChangeKind::from_i32(change.kind), change.msg_id, change.user_id) {
+            (Some(ChangeKind::NewMsg | ChangeKind::MsgEdited | ChangeKind::MsgDeleted), Some(msg_id), _) => {
+                msg_ids.insert(msg_id);
+            }
+            (Some(kind @ (ChangeKind::MemberJoined | ChangeKind::MemberLeft)), _, Some(user_id)) => {
+                members.push(MemberChange { room_id: change.room_id, user_id, joined: kind == ChangeKind::MemberJoined });
+            }
+            _ => {}
+        }
+    }
+    let msg_ids: Vec<i32> = msg_ids.into_iter().collect();
+    let (deleted, mut msgs): (Vec<_>, Vec<_>) = get_chat_msgs_by_ids(&state.pool, &msg_ids).await?
+        .into_iter()
+        .partition(|msg| msg.deleted);
+    msgs.sort_by_key(|msg| msg.id);
+    let deleted = deleted.into_iter()
+        .map(|msg| RspMsgDeleted { room_id: msg.room_id, msg_id: msg.id })
+        .collect();
+    let msgs = client_msgs(&state, user.id, msgs).await?;
+    let rsp = RspSync { cursor, has_more, msgs, deleted, members };
+    ctx.reply(&state, &ServerEvent::RspSync(rsp)).await
+}
@@ -0,0 +1,106 @@
+//! 正在输入提示
+//!
+//! `Typing`/`StopTyping` 不落库，只推送给房间里的其他在线成员。客户端一直没发 `StopTyping`
+//! 时服务端在超时后代为结束；同一用户在同一房间的开始提示有频率限制，避免刷屏。
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::dao::room_dao::get_room_member_ids;
+
+use super::{chatcmd::{send_to, CmdCtx}, chatserver::ChatState, protocol::{ReqTyping, RspTyping, ServerEvent}};
+
+/// 没有新的 Typing 时多久自动结束
+const TYPING_TIMEOUT: Duration = Duration::from_secs(8);
+/// 两次开始提示之间的最短间隔
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+
+#[derive(Debug)]
+pub struct TypingEntry {
+    expires_at: Instant,
+    /// 上一次推送开始提示的时间
+    announced_at: Instant,
+    /// 其他成员是否正看到这个用户在输入
+    announced: bool,
+}
+
+/// (房间 id, 用户 id) -> 输入状态
+pub type TypingMap = Mutex<HashMap<(i32, u64), TypingEntry>>;
+
+pub async fn typing(state: Arc<ChatState>, req: ReqTyping, ctx: &CmdCtx<'_>) -> Result<()> {
+    let key = (req.room_id, ctx.user.id);
+    let now = Instant::now();
+    let (announce, spawn_expiry) = {
+        let mut typing = state.typing.lock().await;
+        match typing.get_mut(&key) {
+            Some(entry) => {
+                entry.expires_at = now + TYPING_TIMEOUT;
+                let announce = !entry.announced && now >= entry.announced_at + TYPING_THROTTLE;
+                if announce {
+                    entry.announced = true;
+                    entry.announced_at = now;
+                }
+                (announce, false)
+            }
+            None => {
+                typing.insert(key, TypingEntry { expires_at: now + TYPING_TIMEOUT, announced_at: now, announced: true });
+                (true, true)
+            }
+        }
+    };
+    if spawn_expiry {
+        tokio::spawn(expire(state.clone(), key, ctx.user.username.clone()));
+    }
+    if announce {
+        notify(&state, req.room_id, ctx.user.id, &ctx.user.username, true).await?;
+    }
+    Ok(())
+}
+
+pub async fn stop_typing(state: Arc<ChatState>, req: ReqTyping, ctx: &CmdCtx<'_>) -> Result<()> {
+    clear(&state, req.room_id, ctx.user.id, &ctx.user.username).await
+}
+
+/// 结束输入提示，发出消息时也会调用；记录保留到超时，用于频率限制
+pub(crate) async fn clear(state: &ChatState, room_id: i32, user_id: u64, user_name: &str) -> Result<()> {
+    let announced = match state.typing.lock().await.get_mut(&(room_id, user_id)) {
+        Some(entry) => std::mem::replace(&mut entry.announced, false),
+        None => false,
+    };
+    if announced {
+        notify(state, room_id, user_id, user_name, false).await?;
+    }
+    Ok(())
+}
+
+/// 等到超时后移除记录，还在提示输入的话推送结束
+async fn expire(state: Arc<ChatState>, key: (i32, u64), user_name: String) {
+    loop {
+        let expires_at = match state.typing.lock().await.get(&key) {
+            Some(entry) => entry.expires_at,
+            None => return,
+        };
+        tokio::time::sleep_until(expires_at).await;
+        let mut typing = state.typing.lock().await;
+        if typing.get(&key).is_some_and(|entry| entry.expires_at <= Instant::now()) {
+            let announced = typing.remove(&key).is_some_and(|entry| entry.announced);
+            drop(typing);
+            if announced {
+                let _ = notify(&state, key.0, key.1, &user_name, false).await;
+            }
+            return;
+        }
+    }
+}
+
+async fn notify(state: &ChatState, room_id: i32, user_id: u64, user_name: &str, typing: bool) -> Result<()> {
+    let event = ServerEvent::TypingChanged(RspTyping { room_id, user_id, user_name: user_name.to_string(), typing });
+    for member in get_room_member_ids(&state.pool, room_id).await? {
+        if member != user_id {
+            send_to(state, member, &event).await?;
+        }
+    }
+    Ok(())
+}
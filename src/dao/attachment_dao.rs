@@ -0,0 +1,107 @@
+use anyhow::Result;
+use sqlx::{MySqlConnection, MySqlPool};
+
+use crate::models::attachment::Attachment;
+
+
+pub async fn get_attachment(pool: &MySqlPool, id: i32) -> Option<Attachment> {
+    sqlx::query_as::<_, Attachment>("SELECT * FROM attachments WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+pub async fn get_attachments(pool: &MySqlPool, ids: &[i32]) -> Result<Vec<Attachment>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT * FROM attachments WHERE id IN ({})",
+        ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, Attachment>(&query);
+    for id in ids {
+        query = query.bind(id);
+    }
+    query.fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// 多条消息的附件，按 id 排序
+pub async fn get_msg_attachments(pool: &MySqlPool, msg_ids: &[i32]) -> Result<Vec<Attachment>> {
+    if msg_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT * FROM attachments WHERE msg_id IN ({}) ORDER BY id",
+        msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, Attachment>(&query);
+    for id in msg_ids {
+        query = query.bind(id);
+    }
+    query.fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+pub struct NewAttachment<'a> {
+    pub uploader: u64,
+    pub name: &'a str,
+    pub size: i64,
+    pub mime: &'a str,
+    pub sha256: &'a str,
+    pub storage_key: &'a str,
+    /// 图片的尺寸
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+pub async fn create_attachment(pool: &MySqlPool, new: &NewAttachment<'_>) -> Result<Attachment> {
+    let id = sqlx::query("INSERT INTO attachments (uploader, name, size, mime, sha256, storage_key, width, height) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+        .bind(new.uploader)
+        .bind(new.name)
+        .bind(new.size)
+        .bind(new.mime)
+        .bind(new.sha256)
+        .bind(new.storage_key)
+        .bind(new.width)
+        .bind(new.height)
+        .execute(pool)
+        .await?
+        .last_insert_id() as i32;
+    get_attachment(pool, id).await.ok_or_else(|| anyhow::anyhow!("attachment not found after insert"))
+}
+
+/// 在消息的事务里把上传者还没发送过的附件关联到消息，返回实际关联的数量
+pub async fn attach_to_msg(tx: &mut MySqlConnection, ids: &[i32], uploader: u64, msg_id: i32, room_id: i32) -> Result<u64, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let query = format!(
+        "UPDATE attachments SET msg_id = ?, room_id = ? WHERE uploader = ? AND msg_id IS NULL AND id IN ({})",
+        ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query(&query).bind(msg_id).bind(room_id).bind(uploader);
+    for id in ids {
+        query = query.bind(id);
+    }
+    query.execute(&mut *tx)
+    .await
+    .map(|r| r.rows_affected())
+}
+
+
+pub async fn update_thumbnail(pool: &MySqlPool, id: i32, thumb_key: &str, width: u32, height: u32) -> Result<()> {
+    sqlx::query("UPDATE attachments SET thumb_key = ?, thumb_width = ?, thumb_height = ? WHERE id = ?")
+        .bind(thumb_key)
+        .bind(width)
+        .bind(height)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
\ No newline at end of file
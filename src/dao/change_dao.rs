@@ -0,0 +1,47 @@
+use anyhow::Result;
+use sqlx::{MySqlExecutor, MySqlPool};
+
+use crate::models::change::{ChangeKind, RoomChange};
+
+
+/// 记录一条房间变化，和引起变化的写操作放在同一个事务里
+pub async fn log_change<'e, E: MySqlExecutor<'e>>(executor: E, room_id: i32, kind: ChangeKind, msg_id: Option<i32>, user_id: Option<u64>) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO room_changes (room_id, kind, msg_id, user_id) VALUES (?, ?, ?, ?)")
+        .bind(room_id)
+        .bind(kind as i32)
+        .bind(msg_id)
+        .bind(user_id)
+        .execute(executor)
+        .await
+        .map(|_| ())
+}
+
+/// 自增 id 在插入时分配，事务可能乱序提交，最近 hold_back 秒内写入的变化及其之后的都还不稳定，
+/// 前面可能还有更早开始的事务没提交。这是取稳定部分 id 上界（不含）的子查询
+const SETTLED_BELOW: &str = "COALESCE((SELECT MIN(id) FROM room_changes WHERE created_at > NOW() - INTERVAL ? SECOND), \
+    (SELECT COALESCE(MAX(id), 0) + 1 FROM room_changes))";
+
+/// 用户所在房间里游标之后的变化，以及用户自己离开房间的记录
+pub async fn get_changes(pool: &MySqlPool, user_id: u64, since: i64, limit: i64) -> Result<Vec<RoomChange>> {
+    sqlx::query_as::<_, RoomChange>("SELECT * FROM room_changes WHERE id > ? \
+        AND (room_id IN (SELECT room_id FROM room_members WHERE user_id = ?) OR (kind = ? AND user_id = ?)) \
+        ORDER BY id LIMIT ?")
+        .bind(since)
+        .bind(user_id)
+        .bind(ChangeKind::MemberLeft as i32)
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// 当前最新的稳定游标：这个 id 及之前的变化都已经提交
+pub async fn latest_change_id(pool: &MySqlPool, hold_back: u64) -> Result<i64> {
+    sqlx::query_scalar::<_, Option<i64>>(&format!("SELECT {} - 1", SETTLED_BELOW))
+        .bind(hold_back)
+        .fetch_one(pool)
+        .await
+        .map(|id| id.unwrap_or(0))
+        .map_err(|e| e.into())
+}
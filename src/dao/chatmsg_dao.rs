@@ -1,39 +1,232 @@
-/**
+/*
  * 根据这个表结构和room.rs编写常规dao方法
  * CREATE TABLE chat_msgs (
     id int PRIMARY KEY AUTO_INCREMENT,
     room_id int NOT NULL,
     message TEXT,
     sender BIGINT,
-    send_time VARCHAR(50)
+    send_time VARCHAR(50),
+    edited_at VARCHAR(50),
+    deleted TINYINT(1) NOT NULL DEFAULT 0,
+    reply_to int,
+    thread_id int,
+    msg_type TINYINT NOT NULL DEFAULT 1
 );
  */
 
- use crate::models::chatmsg::ChatMessage;
- use sqlx::{types::chrono, MySql, Pool};
+ use crate::{dao::{attachment_dao::attach_to_msg, change_dao::log_change}, models::{change::ChangeKind, chatmsg::{ChatMessage, MsgType}}};
+ use sqlx::{types::chrono, MySql, MySqlConnection, Pool};
  
- pub async fn get_chat_msg(pool: &Pool<MySql>, room_id: i32) -> Result<Vec<ChatMessage>, sqlx::Error> {
-     sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_msgs WHERE room_id = ? ORDER BY send_time DESC LIMIT 20")
+ /// id 小于 before 的消息，按 id 倒序，before 为空时从最新一条开始
+ pub async fn get_msgs_before(pool: &Pool<MySql>, room_id: i32, before: Option<i32>, limit: i64) -> Result<Vec<ChatMessage>, sqlx::Error> {
+     sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_msgs WHERE room_id = ? AND id < ? ORDER BY id DESC LIMIT ?")
          .bind(room_id)
+         .bind(before.unwrap_or(i32::MAX))
+         .bind(limit)
          .fetch_all(pool)
          .await
  }
 
- pub async fn get_chat_msg_limit(pool: &Pool<MySql>, room_id: i32, last_id: i32) -> Result<Vec<ChatMessage>, sqlx::Error> {
-     sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_msgs WHERE room_id = ? AND id < ? ORDER BY send_time DESC LIMIT 20")
+ /// id 大于 after 的消息，按 id 正序
+ pub async fn get_msgs_after(pool: &Pool<MySql>, room_id: i32, after: i32, limit: i64) -> Result<Vec<ChatMessage>, sqlx::Error> {
+     sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_msgs WHERE room_id = ? AND id > ? ORDER BY id LIMIT ?")
          .bind(room_id)
-         .bind(last_id)
+         .bind(after)
+         .bind(limit)
          .fetch_all(pool)
          .await
  }
 
- pub async fn create_chat_msg(pool: &Pool<MySql>, room_id: i32, message: &str, sender: u64) -> Result<ChatMessage, sqlx::Error> {
-     sqlx::query_as::<_, ChatMessage>("INSERT INTO chat_msgs (room_id, message, sender, send_time) VALUES (?, ?, ?, ?)")
+ pub async fn get_chat_msg_by_id(pool: &Pool<MySql>, id: i32) -> Result<ChatMessage, sqlx::Error> {
+     sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_msgs WHERE id = ?")
+         .bind(id)
+         .fetch_one(pool)
+         .await
+ }
+
+ pub async fn get_chat_msgs_by_ids(pool: &Pool<MySql>, ids: &[i32]) -> Result<Vec<ChatMessage>, sqlx::Error> {
+     if ids.is_empty() {
+         return Ok(vec![]);
+     }
+     let query = format!(
+         "SELECT * FROM chat_msgs WHERE id IN ({})",
+         ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+     );
+     let mut query = sqlx::query_as::<_, ChatMessage>(&query);
+     for id in ids {
+         query = query.bind(id);
+     }
+     query.fetch_all(pool).await
+ }
+
+ pub async fn create_chat_msg(pool: &Pool<MySql>, room_id: i32, msg_type: MsgType, message: &str, sender: u64) -> Result<ChatMessage, sqlx::Error> {
+     create_reply_msg(pool, room_id, msg_type, message, sender, None).await
+ }
+
+ /// 发送一条回复，reply_to 为被回复的消息，话题根消息取被回复消息所在的话题
+ pub async fn create_reply_msg(pool: &Pool<MySql>, room_id: i32, msg_type: MsgType, message: &str, sender: u64, reply_to: Option<&ChatMessage>) -> Result<ChatMessage, sqlx::Error> {
+     let mut tx = pool.begin().await?;
+     let id = insert_msg(&mut tx, room_id, msg_type, message, sender, reply_to).await?;
+     tx.commit().await?;
+     get_chat_msg_by_id(pool, id).await
+ }
+
+ /// 发送带附件的消息，附件在同一个事务里认领；并发发送同一个附件时只有一条消息能成功，其余回滚并返回 None
+ pub async fn create_msg_with_attachments(pool: &Pool<MySql>, room_id: i32, msg_type: MsgType, message: &str, sender: u64, reply_to: Option<&ChatMessage>, attachments: &[i32]) -> Result<Option<ChatMessage>, sqlx::Error> {
+     let mut tx = pool.begin().await?;
+     let id = insert_msg(&mut tx, room_id, msg_type, message, sender, reply_to).await?;
+     if attach_to_msg(&mut tx, attachments, sender, id, room_id).await? != attachments.len() as u64 {
+         tx.rollback().await?;
+         return Ok(None);
+     }
+     tx.commit().await?;
+     get_chat_msg_by_id(pool, id).await.map(Some)
+ }
+
+ /// 在事务里写入一条消息并记录房间变化，返回消息 id
+ pub async fn insert_msg(tx: &mut MySqlConnection, room_id: i32, msg_type: MsgType, message: &str, sender: u64, reply_to: Option<&ChatMessage>) -> Result<i32, sqlx::Error> {
+     let thread_id = reply_to.map(|msg| msg.thread_id.unwrap_or(msg.id));
+     let id = sqlx::query("INSERT INTO chat_msgs (room_id, message, sender, send_time, reply_to, thread_id, msg_type) VALUES (?, ?, ?, ?, ?, ?, ?)")
          .bind(room_id)
          .bind(message)
          .bind(sender)
-         .bind(chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
-         .fetch_one(pool)
+         .bind(now_str())
+         .bind(reply_to.map(|msg| msg.id))
+         .bind(thread_id)
+         .bind(msg_type as i32)
+         .execute(&mut *tx)
+         .await?
+         .last_insert_id() as i32;
+     log_change(&mut *tx, room_id, ChangeKind::NewMsg, Some(id), Some(sender)).await?;
+     Ok(id)
+ }
+
+ /// 话题内 id 小于 before 的回复，按 id 倒序，before 为空时从最新一条开始
+ pub async fn get_thread_msgs(pool: &Pool<MySql>, thread_id: i32, before: Option<i32>, limit: i64) -> Result<Vec<ChatMessage>, sqlx::Error> {
+     sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_msgs WHERE thread_id = ? AND id < ? ORDER BY id DESC LIMIT ?")
+         .bind(thread_id)
+         .bind(before.unwrap_or(i32::MAX))
+         .bind(limit)
+         .fetch_all(pool)
+         .await
+ }
+
+ /// 每个话题的回复数：(thread_id, count)
+ pub async fn count_thread_replies(pool: &Pool<MySql>, thread_ids: &[i32]) -> Result<Vec<(i32, i64)>, sqlx::Error> {
+     if thread_ids.is_empty() {
+         return Ok(vec![]);
+     }
+     let query = format!(
+         "SELECT thread_id, COUNT(*) FROM chat_msgs WHERE deleted = 0 AND thread_id IN ({}) GROUP BY thread_id",
+         thread_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+     );
+     let mut query = sqlx::query_as::<_, (i32, i64)>(&query);
+     for id in thread_ids {
+         query = query.bind(id);
+     }
+     query.fetch_all(pool).await
+ }
+
+ /// 用户在各个房间的未读数：(room_id, count)，自己发的消息不算未读
+ pub async fn count_unread(pool: &Pool<MySql>, user_id: u64) -> Result<Vec<(i32, i64)>, sqlx::Error> {
+     sqlx::query_as::<_, (i32, i64)>("SELECT m.room_id, COUNT(c.id) FROM room_members m \
+         JOIN chat_msgs c ON c.room_id = m.room_id AND c.id > m.last_read_id AND c.deleted = 0 AND c.sender <> m.user_id \
+         WHERE m.user_id = ? GROUP BY m.room_id")
+         .bind(user_id)
+         .fetch_all(pool)
+         .await
+ }
+
+ /// 每个房间的最后一条消息
+ pub async fn get_last_msgs(pool: &Pool<MySql>, room_ids: &[i32]) -> Result<Vec<ChatMessage>, sqlx::Error> {
+     if room_ids.is_empty() {
+         return Ok(vec![]);
+     }
+     let query = format!(
+         "SELECT * FROM chat_msgs WHERE id IN (SELECT MAX(id) FROM chat_msgs WHERE room_id IN ({}) GROUP BY room_id)",
+         room_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+     );
+     let mut query = sqlx::query_as::<_, ChatMessage>(&query);
+     for id in room_ids {
+         query = query.bind(id);
+     }
+     query.fetch_all(pool).await
+ }
+
+ /// 消息搜索条件，query 是 BOOLEAN MODE 的全文检索表达式，时间是 send_time 格式的字符串
+ pub struct MsgSearch<'a> {
+     pub query: &'a str,
+     pub room_id: Option<i32>,
+     pub sender: Option<u64>,
+     pub from: Option<&'a str>,
+     pub to: Option<&'a str>,
+     pub last_id: Option<i32>,
+     pub limit: i64,
+ }
+
+ /// 在用户所在的房间里搜索未撤回的消息，按 id 倒序
+ pub async fn search_chat_msgs(pool: &Pool<MySql>, user_id: u64, search: &MsgSearch<'_>) -> Result<Vec<ChatMessage>, sqlx::Error> {
+     sqlx::query_as::<_, ChatMessage>("SELECT c.* FROM chat_msgs c JOIN room_members m ON m.room_id = c.room_id AND m.user_id = ? \
+         WHERE MATCH(c.message) AGAINST (? IN BOOLEAN MODE) AND c.deleted = 0 AND c.id < ? \
+         AND (? IS NULL OR c.room_id = ?) AND (? IS NULL OR c.sender = ?) \
+         AND (? IS NULL OR c.send_time >= ?) AND (? IS NULL OR c.send_time <= ?) \
+         ORDER BY c.id DESC LIMIT ?")
+         .bind(user_id)
+         .bind(search.query)
+         .bind(search.last_id.unwrap_or(i32::MAX))
+         .bind(search.room_id)
+         .bind(search.room_id)
+         .bind(search.sender)
+         .bind(search.sender)
+         .bind(search.from)
+         .bind(search.from)
+         .bind(search.to)
+         .bind(search.to)
+         .bind(search.limit)
+         .fetch_all(pool)
          .await
  }
 
+ fn now_str() -> String {
+     chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+ }
+
+ /// 修改消息内容，旧内容记入 chat_msg_edits
+ pub async fn edit_chat_msg(pool: &Pool<MySql>, msg: &ChatMessage, message: &str, editor: u64) -> Result<ChatMessage, sqlx::Error> {
+     let now = now_str();
+     let mut tx = pool.begin().await?;
+     sqlx::query("INSERT INTO chat_msg_edits (msg_id, old_message, editor, edit_time) VALUES (?, ?, ?, ?)")
+         .bind(msg.id)
+         .bind(&msg.message)
+         .bind(editor)
+         .bind(&now)
+         .execute(&mut *tx)
+         .await?;
+     sqlx::query("UPDATE chat_msgs SET message = ?, edited_at = ? WHERE id = ?")
+         .bind(message)
+         .bind(&now)
+         .bind(msg.id)
+         .execute(&mut *tx)
+         .await?;
+     log_change(&mut *tx, msg.room_id, ChangeKind::MsgEdited, Some(msg.id), Some(editor)).await?;
+     tx.commit().await?;
+     get_chat_msg_by_id(pool, msg.id).await
+ }
+
+ /// 撤回消息，清空内容并把原内容记入 chat_msg_edits
+ pub async fn delete_chat_msg(pool: &Pool<MySql>, msg: &ChatMessage, operator: u64) -> Result<(), sqlx::Error> {
+     let mut tx = pool.begin().await?;
+     sqlx::query("INSERT INTO chat_msg_edits (msg_id, old_message, editor, edit_time) VALUES (?, ?, ?, ?)")
+         .bind(msg.id)
+         .bind(&msg.message)
+         .bind(operator)
+         .bind(now_str())
+         .execute(&mut *tx)
+         .await?;
+     sqlx::query("UPDATE chat_msgs SET message = '', deleted = 1 WHERE id = ?")
+         .bind(msg.id)
+         .execute(&mut *tx)
+         .await?;
+     log_change(&mut *tx, msg.room_id, ChangeKind::MsgDeleted, Some(msg.id), Some(operator)).await?;
+     tx.commit().await
+ }
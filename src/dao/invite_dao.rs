@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use sqlx::{types::chrono::{Local, NaiveDateTime}, MySqlPool};
+
+use crate::models::invite::{InviteStatus, RoomInvite};
+
+
+pub async fn get_invite(pool: &MySqlPool, id: i32) -> Option<RoomInvite> {
+    sqlx::query_as::<_, RoomInvite>("SELECT * FROM room_invites WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+pub async fn get_invite_by_code(pool: &MySqlPool, code: &str) -> Option<RoomInvite> {
+    sqlx::query_as::<_, RoomInvite>("SELECT * FROM room_invites WHERE code = ?")
+        .bind(code)
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+/// 邀请最长有效期，超过的按这个值算
+const MAX_EXPIRES_IN: u64 = 60 * 60 * 24 * 365 * 10;
+
+/// 过期时间都按应用服务器的本地时间计算和比较，和 `RoomInvite::is_expired` 一致，不依赖数据库的时区
+fn now() -> NaiveDateTime {
+    Local::now().naive_local()
+}
+
+/// 某个用户在某个房间还没处理的邀请
+pub async fn get_pending_invite(pool: &MySqlPool, room_id: i32, invitee: u64) -> Option<RoomInvite> {
+    sqlx::query_as::<_, RoomInvite>("SELECT * FROM room_invites WHERE room_id = ? AND invitee = ? AND status = ? AND (expires_at IS NULL OR expires_at > ?) LIMIT 1")
+        .bind(room_id)
+        .bind(invitee)
+        .bind(InviteStatus::Pending as i32)
+        .bind(now())
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+pub async fn get_pending_invites(pool: &MySqlPool, invitee: u64) -> Result<Vec<RoomInvite>> {
+    sqlx::query_as::<_, RoomInvite>("SELECT * FROM room_invites WHERE invitee = ? AND status = ? AND (expires_at IS NULL OR expires_at > ?) ORDER BY id DESC")
+        .bind(invitee)
+        .bind(InviteStatus::Pending as i32)
+        .bind(now())
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// 创建邀请，invitee 和 code 二选一，expires_in 为有效秒数
+pub async fn create_invite(pool: &MySqlPool, room_id: i32, inviter: u64, invitee: Option<u64>, code: Option<&str>, expires_in: Option<u64>) -> Result<RoomInvite> {
+    let expires_at = expires_in.map(|secs| now() + Duration::from_secs(secs.min(MAX_EXPIRES_IN)));
+    let id = sqlx::query("INSERT INTO room_invites (room_id, inviter, invitee, code, expires_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(room_id)
+        .bind(inviter)
+        .bind(invitee)
+        .bind(code)
+        .bind(expires_at)
+        .execute(pool)
+        .await?
+        .last_insert_id() as i32;
+    get_invite(pool, id).await.ok_or_else(|| anyhow::anyhow!("invite not found after insert"))
+}
+
+pub async fn update_invite_status(pool: &MySqlPool, id: i32, status: InviteStatus) -> Result<()> {
+    sqlx::query("UPDATE room_invites SET status = ? WHERE id = ?")
+        .bind(status as i32)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
\ No newline at end of file
@@ -0,0 +1,68 @@
+use anyhow::Result;
+use sqlx::MySqlPool;
+
+use crate::models::{chatmsg::ChatMessage, mention::{Mention, MentionKind}};
+
+
+/// 为一条消息记录被提及的用户，返回这条消息的所有提及
+pub async fn create_mentions(pool: &MySqlPool, msg: &ChatMessage, targets: &[(u64, MentionKind)]) -> Result<Vec<Mention>> {
+    if targets.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "INSERT IGNORE INTO mentions (msg_id, room_id, user_id, sender, kind) VALUES {}",
+        targets.iter().map(|_| "(?, ?, ?, ?, ?)").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query(&query);
+    for (user_id, kind) in targets {
+        query = query.bind(msg.id)
+            .bind(msg.room_id)
+            .bind(user_id)
+            .bind(msg.sender)
+            .bind(*kind as i32);
+    }
+    query.execute(pool).await?;
+    sqlx::query_as::<_, Mention>("SELECT * FROM mentions WHERE msg_id = ?")
+        .bind(msg.id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// 未读的提及，按 id 倒序每次 20 条；已撤回的消息和已离开的房间不再返回
+pub async fn get_unread_mentions(pool: &MySqlPool, user_id: u64, last_id: Option<i32>) -> Result<Vec<Mention>> {
+    sqlx::query_as::<_, Mention>("SELECT m.* FROM mentions m \
+        JOIN chat_msgs c ON c.id = m.msg_id \
+        JOIN room_members rm ON rm.room_id = m.room_id AND rm.user_id = m.user_id \
+        WHERE m.user_id = ? AND m.is_read = 0 AND c.deleted = 0 AND m.id < ? \
+        ORDER BY m.id DESC LIMIT 20")
+        .bind(user_id)
+        .bind(last_id.unwrap_or(i32::MAX))
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// 标记提及为已读，room_id 为空时标记全部
+pub async fn mark_mentions_read(pool: &MySqlPool, user_id: u64, room_id: Option<i32>) -> Result<u64> {
+    sqlx::query("UPDATE mentions SET is_read = 1 WHERE user_id = ? AND is_read = 0 AND (? IS NULL OR room_id = ?)")
+        .bind(user_id)
+        .bind(room_id)
+        .bind(room_id)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected())
+        .map_err(|e| e.into())
+}
+
+/// 读到某条消息时，房间里这条及之前的提及都算已读
+pub async fn mark_mentions_read_until(pool: &MySqlPool, user_id: u64, room_id: i32, msg_id: i32) -> Result<u64> {
+    sqlx::query("UPDATE mentions SET is_read = 1 WHERE user_id = ? AND room_id = ? AND msg_id <= ? AND is_read = 0")
+        .bind(user_id)
+        .bind(room_id)
+        .bind(msg_id)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected())
+        .map_err(|e| e.into())
+}
@@ -1,4 +1,10 @@
 
 pub mod user_dao;
 pub mod room_dao;
-pub mod chatmsg_dao;
\ No newline at end of file
+pub mod chatmsg_dao;
+pub mod invite_dao;
+pub mod reaction_dao;
+pub mod mention_dao;
+pub mod change_dao;
+pub mod attachment_dao;
+pub mod poll_dao;
\ No newline at end of file
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use sqlx::{types::chrono::NaiveDateTime, MySqlPool};
+
+use crate::{dao::{change_dao::log_change, chatmsg_dao::insert_msg}, models::{change::ChangeKind, chatmsg::MsgType, poll::{Poll, PollOption}}};
+
+
+/// 新投票的内容，选项按顺序保存
+pub struct NewPoll<'a> {
+    pub room_id: i32,
+    pub creator: u64,
+    pub question: &'a str,
+    pub options: &'a [String],
+    pub multi_choice: bool,
+    pub closes_at: Option<NaiveDateTime>,
+}
+
+/// 在同一个事务里写入投票消息、投票和选项，返回投票
+pub async fn create_poll(pool: &MySqlPool, new: &NewPoll<'_>) -> Result<Poll> {
+    let mut tx = pool.begin().await?;
+    let msg_id = insert_msg(&mut tx, new.room_id, MsgType::Poll, new.question, new.creator, None).await?;
+    let id = sqlx::query("INSERT INTO polls (msg_id, room_id, creator, question, multi_choice, closes_at) VALUES (?, ?, ?, ?, ?, ?)")
+        .bind(msg_id)
+        .bind(new.room_id)
+        .bind(new.creator)
+        .bind(new.question)
+        .bind(new.multi_choice)
+        .bind(new.closes_at)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id() as i32;
+    for (position, text) in new.options.iter().enumerate() {
+        sqlx::query("INSERT INTO poll_options (poll_id, position, text) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(position as i32)
+            .bind(text)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    get_poll(pool, id).await.ok_or_else(|| anyhow::anyhow!("poll not found"))
+}
+
+pub async fn get_poll(pool: &MySqlPool, id: i32) -> Option<Poll> {
+    sqlx::query_as::<_, Poll>("SELECT * FROM polls WHERE id = ?")
+        .bind(id)
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+pub async fn get_polls_by_msgs(pool: &MySqlPool, msg_ids: &[i32]) -> Result<Vec<Poll>> {
+    if msg_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT * FROM polls WHERE msg_id IN ({})",
+        msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, Poll>(&query);
+    for id in msg_ids {
+        query = query.bind(id);
+    }
+    query.fetch_all(pool).await.map_err(|e| e.into())
+}
+
+/// 设置了截止时间但还没有关闭的投票
+pub async fn get_open_polls_with_deadline(pool: &MySqlPool) -> Result<Vec<Poll>> {
+    sqlx::query_as::<_, Poll>("SELECT * FROM polls WHERE closed = 0 AND closes_at IS NOT NULL")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+pub async fn get_poll_options(pool: &MySqlPool, poll_ids: &[i32]) -> Result<Vec<PollOption>> {
+    if poll_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT * FROM poll_options WHERE poll_id IN ({}) ORDER BY poll_id, position",
+        poll_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, PollOption>(&query);
+    for id in poll_ids {
+        query = query.bind(id);
+    }
+    query.fetch_all(pool).await.map_err(|e| e.into())
+}
+
+/// 按选项聚合：(option_id, count, viewer 是否投过)
+pub async fn count_votes(pool: &MySqlPool, poll_ids: &[i32], viewer: u64) -> Result<Vec<(i32, i64, bool)>> {
+    if poll_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT option_id, COUNT(*), CAST(SUM(user_id = ?) AS SIGNED) FROM poll_votes WHERE poll_id IN ({}) GROUP BY option_id",
+        poll_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, (i32, i64, i64)>(&query).bind(viewer);
+    for id in poll_ids {
+        query = query.bind(id);
+    }
+    let rows = query.fetch_all(pool).await?;
+    Ok(rows.into_iter().map(|(option_id, count, me)| (option_id, count, me > 0)).collect())
+}
+
+/// 每个投票的投票人数：(poll_id, count)
+pub async fn count_voters(pool: &MySqlPool, poll_ids: &[i32]) -> Result<Vec<(i32, i64)>> {
+    if poll_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT poll_id, COUNT(DISTINCT user_id) FROM poll_votes WHERE poll_id IN ({}) GROUP BY poll_id",
+        poll_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, (i32, i64)>(&query);
+    for id in poll_ids {
+        query = query.bind(id);
+    }
+    query.fetch_all(pool).await.map_err(|e| e.into())
+}
+
+/// 用新的选择替换用户之前的投票，option_ids 为空表示撤回；投票消息记为编辑过，离线的客户端同步时会拿到最新票数
+pub async fn set_votes(pool: &MySqlPool, poll: &Poll, user_id: u64, option_ids: &[i32]) -> Result<()> {
+    let poll_id = poll.id;
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM poll_votes WHERE poll_id = ? AND user_id = ?")
+        .bind(poll_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for option_id in option_ids {
+        sqlx::query("INSERT INTO poll_votes (poll_id, option_id, user_id) VALUES (?, ?, ?)")
+            .bind(poll_id)
+            .bind(option_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    log_change(&mut *tx, poll.room_id, ChangeKind::MsgEdited, Some(poll.msg_id), Some(user_id)).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// 关闭投票，返回是否真的关闭了（已经关闭的返回 false）；投票消息记为编辑过，离线的客户端同步时会重新拉取
+pub async fn close_poll(pool: &MySqlPool, poll: &Poll) -> Result<bool> {
+    let mut tx = pool.begin().await?;
+    let closed = sqlx::query("UPDATE polls SET closed = 1 WHERE id = ? AND closed = 0")
+        .bind(poll.id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() > 0;
+    if closed {
+        log_change(&mut *tx, poll.room_id, ChangeKind::MsgEdited, Some(poll.msg_id), None).await?;
+    }
+    tx.commit().await?;
+    Ok(closed)
+}
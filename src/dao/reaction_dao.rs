@@ -0,0 +1,53 @@
+use anyhow::Result;
+use sqlx::MySqlPool;
+
+
+/// 返回是否新增了回应（重复添加返回 false）
+pub async fn add_reaction(pool: &MySqlPool, msg_id: i32, user_id: u64, emoji: &str) -> Result<bool> {
+    sqlx::query("INSERT IGNORE INTO message_reactions (msg_id, user_id, emoji) VALUES (?, ?, ?)")
+        .bind(msg_id)
+        .bind(user_id)
+        .bind(emoji)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected() > 0)
+        .map_err(|e| e.into())
+}
+
+/// 返回是否真的删除了回应
+pub async fn remove_reaction(pool: &MySqlPool, msg_id: i32, user_id: u64, emoji: &str) -> Result<bool> {
+    sqlx::query("DELETE FROM message_reactions WHERE msg_id = ? AND user_id = ? AND emoji = ?")
+        .bind(msg_id)
+        .bind(user_id)
+        .bind(emoji)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected() > 0)
+        .map_err(|e| e.into())
+}
+
+pub async fn count_reaction(pool: &MySqlPool, msg_id: i32, emoji: &str) -> Result<i64> {
+    sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM message_reactions WHERE msg_id = ? AND emoji = ?")
+        .bind(msg_id)
+        .bind(emoji)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// 按消息和表情聚合：(msg_id, emoji, count, viewer 是否回应过)
+pub async fn get_reactions(pool: &MySqlPool, msg_ids: &[i32], viewer: u64) -> Result<Vec<(i32, String, i64, bool)>> {
+    if msg_ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT msg_id, emoji, COUNT(*), CAST(SUM(user_id = ?) AS SIGNED) FROM message_reactions WHERE msg_id IN ({}) GROUP BY msg_id, emoji ORDER BY MIN(created_at)",
+        msg_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, (i32, String, i64, i64)>(&query).bind(viewer);
+    for id in msg_ids {
+        query = query.bind(id);
+    }
+    let rows = query.fetch_all(pool).await?;
+    Ok(rows.into_iter().map(|(msg_id, emoji, count, mine)| (msg_id, emoji, count, mine > 0)).collect())
+}
\ No newline at end of file
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use sqlx::MySqlPool;
 
-use crate::models::room::Room;
+use crate::{dao::change_dao::log_change, models::{change::ChangeKind, room::Room, room_member::{RoomMember, RoomRole}}};
 
 
 pub async fn get_room(pool: &MySqlPool, id: i32) -> Option<Room> {
@@ -20,21 +20,36 @@ pub async fn get_room_by_name(pool: &MySqlPool, room_name: &str) -> Option<Room>
         .ok()
 }
 
-pub async fn create_room(pool: &MySqlPool, room_type: i32, room_name: &str, members: &Vec<u64>) -> Result<Room> {
-    let members = serde_json::to_string(&members)?;
-    sqlx::query_as::<_, Room>("INSERT INTO rooms (room_type, room_name, members) VALUES (?, ?, ?)")
+/// 创建房间并写入成员，创建者记为 owner
+pub async fn create_room(pool: &MySqlPool, room_type: i32, room_name: &str, owner: u64, members: &[u64]) -> Result<Room> {
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query("INSERT INTO rooms (room_type, room_name) VALUES (?, ?)")
         .bind(room_type)
         .bind(room_name)
-        .bind(members)
-        .fetch_one(pool)
-        .await
-        .map_err(|e| e.into())
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id() as i32;
+    for member in members {
+        let role = if *member == owner { RoomRole::Owner } else { RoomRole::Member };
+        sqlx::query("INSERT IGNORE INTO room_members (room_id, user_id, role) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(member)
+            .bind(role as i32)
+            .execute(&mut *tx)
+            .await?;
+        log_change(&mut *tx, id, ChangeKind::MemberJoined, None, Some(*member)).await?;
+    }
+    tx.commit().await?;
+    Ok(Room {
+        id,
+        room_type,
+        room_name: room_name.to_string(),
+    })
 }
 
-pub async fn update_room_members(pool: &MySqlPool, id: i32, members: Vec<u64>) -> Result<()> {
-    let members = serde_json::to_string(&members)?;
-    sqlx::query("UPDATE rooms SET members = ? WHERE id = ?")
-        .bind(members)
+pub async fn update_room_name(pool: &MySqlPool, id: i32, room_name: &str) -> Result<()> {
+    sqlx::query("UPDATE rooms SET room_name = ? WHERE id = ?")
+        .bind(room_name)
         .bind(id)
         .execute(pool)
         .await
@@ -43,12 +58,45 @@ pub async fn update_room_members(pool: &MySqlPool, id: i32, members: Vec<u64>) -
 }
 
 pub async fn delete_room(pool: &MySqlPool, id: i32) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    // 先删消息的附属数据，再删消息本身
+    for sql in [
+        "DELETE v FROM poll_votes v JOIN polls p ON p.id = v.poll_id WHERE p.room_id = ?",
+        "DELETE o FROM poll_options o JOIN polls p ON p.id = o.poll_id WHERE p.room_id = ?",
+        "DELETE FROM polls WHERE room_id = ?",
+        "DELETE r FROM message_reactions r JOIN chat_msgs m ON m.id = r.msg_id WHERE m.room_id = ?",
+        "DELETE e FROM chat_msg_edits e JOIN chat_msgs m ON m.id = e.msg_id WHERE m.room_id = ?",
+        "DELETE FROM mentions WHERE room_id = ?",
+        "DELETE FROM attachments WHERE room_id = ?",
+        "DELETE FROM chat_msgs WHERE room_id = ?",
+        "DELETE FROM room_changes WHERE room_id = ?",
+    ] {
+        sqlx::query(sql).bind(id).execute(&mut *tx).await?;
+    }
+    // 房间删除后成员查不到房间里的变化，逐个记为离开，离线的成员同步时才知道房间没了
+    sqlx::query("INSERT INTO room_changes (room_id, kind, user_id) SELECT room_id, ?, user_id FROM room_members WHERE room_id = ?")
+        .bind(ChangeKind::MemberLeft as i32)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM room_members WHERE room_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM room_invites WHERE room_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM direct_rooms WHERE room_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
     sqlx::query("DELETE FROM rooms WHERE id = ?")
         .bind(id)
-        .execute(pool)
-        .await
-        .map(|_| ())
-        .map_err(|e| e.into())
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
 }
 
 pub async fn get_rooms(pool: &MySqlPool) -> Result<Vec<Room>> {
@@ -58,9 +106,9 @@ pub async fn get_rooms(pool: &MySqlPool) -> Result<Vec<Room>> {
         .map_err(|e| e.into())
 }
 
-pub async fn get_rooms_by_member(pool: &MySqlPool, member: &str) -> Result<Vec<Room>> {
-    sqlx::query_as::<_, Room>("SELECT * FROM rooms WHERE members LIKE ?")
-        .bind(format!("%{}%", member))
+pub async fn get_rooms_by_member(pool: &MySqlPool, user_id: u64) -> Result<Vec<Room>> {
+    sqlx::query_as::<_, Room>("SELECT r.* FROM rooms r JOIN room_members m ON m.room_id = r.id WHERE m.user_id = ?")
+        .bind(user_id)
         .fetch_all(pool)
         .await
         .map_err(|e| e.into())
@@ -72,4 +120,162 @@ pub async fn get_rooms_by_type(pool: &MySqlPool, room_type: i32) -> Result<Vec<R
         .fetch_all(pool)
         .await
         .map_err(|e| e.into())
+}
+
+/// 加入房间，已经是成员时不改变原有角色
+pub async fn add_room_member(pool: &MySqlPool, room_id: i32, user_id: u64, role: RoomRole) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let added = sqlx::query("INSERT IGNORE INTO room_members (room_id, user_id, role) VALUES (?, ?, ?)")
+        .bind(room_id)
+        .bind(user_id)
+        .bind(role as i32)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() > 0;
+    if added {
+        log_change(&mut *tx, room_id, ChangeKind::MemberJoined, None, Some(user_id)).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_room_member(pool: &MySqlPool, room_id: i32, user_id: u64) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let removed = sqlx::query("DELETE FROM room_members WHERE room_id = ? AND user_id = ?")
+        .bind(room_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected() > 0;
+    if removed {
+        log_change(&mut *tx, room_id, ChangeKind::MemberLeft, None, Some(user_id)).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_room_member(pool: &MySqlPool, room_id: i32, user_id: u64) -> Option<RoomMember> {
+    sqlx::query_as::<_, RoomMember>("SELECT * FROM room_members WHERE room_id = ? AND user_id = ?")
+        .bind(room_id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+pub async fn get_room_members(pool: &MySqlPool, room_id: i32) -> Result<Vec<RoomMember>> {
+    sqlx::query_as::<_, RoomMember>("SELECT * FROM room_members WHERE room_id = ? ORDER BY joined_at")
+        .bind(room_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+pub async fn get_room_member_ids(pool: &MySqlPool, room_id: i32) -> Result<Vec<u64>> {
+    sqlx::query_scalar::<_, u64>("SELECT user_id FROM room_members WHERE room_id = ?")
+        .bind(room_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// 和某个用户至少共享一个房间的其他用户
+pub async fn get_room_peers(pool: &MySqlPool, user_id: u64) -> Result<Vec<u64>> {
+    sqlx::query_scalar::<_, u64>("SELECT DISTINCT o.user_id FROM room_members m JOIN room_members o ON o.room_id = m.room_id WHERE m.user_id = ? AND o.user_id <> ?")
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+}
+
+pub async fn update_member_role(pool: &MySqlPool, room_id: i32, user_id: u64, role: RoomRole) -> Result<()> {
+    sqlx::query("UPDATE room_members SET role = ? WHERE room_id = ? AND user_id = ?")
+        .bind(role as i32)
+        .bind(room_id)
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+fn direct_key(a: u64, b: u64) -> (u64, u64) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+/// 已读指针只前进不后退，返回是否有变化
+pub async fn update_last_read(pool: &MySqlPool, room_id: i32, user_id: u64, msg_id: i32) -> Result<bool> {
+    sqlx::query("UPDATE room_members SET last_read_id = ? WHERE room_id = ? AND user_id = ? AND last_read_id < ?")
+        .bind(msg_id)
+        .bind(room_id)
+        .bind(user_id)
+        .bind(msg_id)
+        .execute(pool)
+        .await
+        .map(|r| r.rows_affected() > 0)
+        .map_err(|e| e.into())
+}
+
+pub async fn get_direct_room(pool: &MySqlPool, a: u64, b: u64) -> Option<Room> {
+    let (low, high) = direct_key(a, b);
+    sqlx::query_as::<_, Room>("SELECT r.* FROM rooms r JOIN direct_rooms d ON d.room_id = r.id WHERE d.user_low = ? AND d.user_high = ?")
+        .bind(low)
+        .bind(high)
+        .fetch_one(pool)
+        .await
+        .ok()
+}
+
+/// 创建两人的私聊房间；并发创建时唯一键冲突的一方回滚并返回已存在的房间
+pub async fn create_direct_room(pool: &MySqlPool, room_type: i32, a: u64, b: u64) -> Result<Room> {
+    let (low, high) = direct_key(a, b);
+    let room_name = format!("{}-{}", low, high);
+    let mut tx = pool.begin().await?;
+    let id = sqlx::query("INSERT INTO rooms (room_type, room_name) VALUES (?, ?)")
+        .bind(room_type)
+        .bind(&room_name)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_id() as i32;
+    let r = sqlx::query("INSERT INTO direct_rooms (user_low, user_high, room_id) VALUES (?, ?, ?)")
+        .bind(low)
+        .bind(high)
+        .bind(id)
+        .execute(&mut *tx)
+        .await;
+    match r {
+        Ok(_) => {}
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            tx.rollback().await?;
+            return get_direct_room(pool, low, high).await.ok_or_else(|| anyhow::anyhow!("direct room not found"));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    for member in [low, high] {
+        sqlx::query("INSERT IGNORE INTO room_members (room_id, user_id, role) VALUES (?, ?, ?)")
+            .bind(id)
+            .bind(member)
+            .bind(RoomRole::Member as i32)
+            .execute(&mut *tx)
+            .await?;
+        log_change(&mut *tx, id, ChangeKind::MemberJoined, None, Some(member)).await?;
+    }
+    tx.commit().await?;
+    Ok(Room {
+        id,
+        room_type,
+        room_name,
+    })
+}
+
+/// 用户所有私聊房间及对方的 id：(room_id, peer_id)
+pub async fn get_direct_peers(pool: &MySqlPool, user_id: u64) -> Result<Vec<(i32, u64)>> {
+    sqlx::query_as::<_, (i32, u64)>("SELECT room_id, IF(user_low = ?, user_high, user_low) FROM direct_rooms WHERE user_low = ? OR user_high = ?")
+        .bind(user_id)
+        .bind(user_id)
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use sqlx::MySqlPool;
 
-use crate::models::user::User;
+use crate::models::{presence::UserPresence, user::User};
 
 
 pub async fn get_user(pool: &MySqlPool, id: u64) -> Option<User> {
@@ -21,6 +21,9 @@ pub async fn get_user_by_name(pool: &MySqlPool, username: &str) -> Option<User>
 }
 
 pub async fn get_user_in_id(pool: &MySqlPool, ids: &Vec<u64>) -> Result<Vec<User>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
     let query = format!(
         "SELECT * FROM users WHERE id IN ({})",
         ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
@@ -34,6 +37,23 @@ pub async fn get_user_in_id(pool: &MySqlPool, ids: &Vec<u64>) -> Result<Vec<User
     .map_err(|e| e.into())
 }
 
+pub async fn get_users_by_names(pool: &MySqlPool, names: &[String]) -> Result<Vec<User>> {
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT * FROM users WHERE username IN ({})",
+        names.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, User>(&query);
+    for name in names {
+        query = query.bind(name);
+    }
+    query.fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
 pub async fn insert_user(pool: &MySqlPool, username: &str, password_hash: &str) -> Result<()> {
     sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, ?)")
         .bind(username)
@@ -52,4 +72,42 @@ pub async fn update_user_password(pool: &MySqlPool, id: u64, password_hash: &str
         .await
         .map(|_| ())
         .map_err(|e| e.into())
+}
+
+pub async fn get_presences(pool: &MySqlPool, ids: &[u64]) -> Result<Vec<UserPresence>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+    let query = format!(
+        "SELECT id, status, status_text, last_seen_at FROM users WHERE id IN ({})",
+        ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+    );
+    let mut query = sqlx::query_as::<_, UserPresence>(&query);
+    for id in ids {
+        query = query.bind(id);
+    }
+    query.fetch_all(pool)
+    .await
+    .map_err(|e| e.into())
+}
+
+/// 客户端设置的状态和自定义文字
+pub async fn update_status(pool: &MySqlPool, id: u64, status: i32, status_text: Option<&str>) -> Result<()> {
+    sqlx::query("UPDATE users SET status = ?, status_text = ? WHERE id = ?")
+        .bind(status)
+        .bind(status_text)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
+}
+
+pub async fn update_last_seen(pool: &MySqlPool, id: u64) -> Result<()> {
+    sqlx::query("UPDATE users SET last_seen_at = NOW() WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.into())
 }
\ No newline at end of file
@@ -0,0 +1,187 @@
+use actix_web::{get, rt, http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue}, post, web, HttpRequest, HttpResponse, Responder};
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use serde::Deserialize;
+use image::ImageFormat;
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+
+use crate::{chat::{attachment::attachment_info, protocol::AttachmentInfo}, dao::{attachment_dao::{create_attachment, get_attachment, update_thumbnail, NewAttachment}, chatmsg_dao::get_chat_msg_by_id, room_dao::get_room_member}, models::attachment::Attachment, storage::BlobStore, utils::{image::{image_size, make_thumbnail, sniff_image}, random::random_hex}, web::{auth::ClaimsExtractor, common::{ApiResponse, AppState, ErrorCode}}};
+
+/// 单个文件的大小上限
+const MAX_UPLOAD_SIZE: usize = 20 * 1024 * 1024;
+/// 允许上传的文件类型
+const ALLOWED_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+    "audio/mpeg",
+    "video/mp4",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    pub name: String,
+}
+
+/// 请求体就是文件内容，文件类型取自 Content-Type，文件名放在 `?name=` 中。
+/// 返回的附件 id 在 SendMsg 的 attachments 中使用。
+#[post("/upload")]
+pub async fn upload(state: web::Data<AppState>, req: HttpRequest, query: web::Query<UploadQuery>, payload: web::Payload, claims: ClaimsExtractor) -> impl Responder {
+    let mime = req.headers().get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    match _upload(&state, &query.name, &mime, payload, claims.sub).await {
+        Ok(info) => HttpResponse::Ok().json(ApiResponse::success(info)),
+        Err(e) => {
+            log::error!("上传失败: {}", e);
+            HttpResponse::Ok().json(ApiResponse::from_err(&e, format!("上传失败：{}", e)))
+        }
+    }
+}
+
+async fn _upload(state: &AppState, name: &str, mime: &str, mut payload: web::Payload, uploader: u64) -> Result<AttachmentInfo> {
+    // 只保留文件名部分
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    if name.is_empty() || name.chars().count() > 255 {
+        return Err(ErrorCode::BadRequest.error("invalid file name"));
+    }
+    if !ALLOWED_TYPES.contains(&mime) {
+        return Err(ErrorCode::FileTypeNotAllowed.error(format!("file type not allowed:{}", mime)));
+    }
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| ErrorCode::BadRequest.error(e.to_string()))?;
+        if body.len() + chunk.len() > MAX_UPLOAD_SIZE {
+            return Err(ErrorCode::FileTooLarge.error(format!("file is larger than {} bytes", MAX_UPLOAD_SIZE)));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    if body.is_empty() {
+        return Err(ErrorCode::BadRequest.error("file is empty"));
+    }
+    // 图片按文件头再确认一次，防止伪造类型
+    if mime.starts_with("image/") && sniff_image(&body) != Some(mime) {
+        return Err(ErrorCode::FileTypeNotAllowed.error("file content does not match its type"));
+    }
+    let (width, height) = if mime.starts_with("image/") {
+        let (width, height) = image_size(&body).map_err(|e| ErrorCode::BadRequest.error(format!("invalid image:{}", e)))?;
+        (Some(width), Some(height))
+    } else {
+        (None, None)
+    };
+    let sha256 = hex::encode(Sha256::digest(&body));
+    let storage_key = format!("attachments/{}/{}", &sha256[..2], random_hex(16));
+    let size = body.len() as i64;
+    let data = body.freeze();
+    state.blobs.put(&storage_key, data.clone()).await?;
+    let attachment = create_attachment(&state.pool, &NewAttachment {
+        uploader,
+        name,
+        size,
+        mime,
+        sha256: &sha256,
+        storage_key: &storage_key,
+        width,
+        height,
+    }).await?;
+    if width.is_some() {
+        let pool = state.pool.clone();
+        let blobs = state.blobs.clone();
+        let attachment = attachment.clone();
+        rt::spawn(async move {
+            if let Err(e) = generate_thumbnail(&pool, blobs.as_ref(), &attachment, data).await {
+                log::error!("生成缩略图失败: {}, {}", attachment.id, e);
+            }
+        });
+    }
+    Ok(attachment_info(&attachment))
+}
+
+/// 在后台生成缩略图，存在原文件旁边
+async fn generate_thumbnail(pool: &MySqlPool, blobs: &dyn BlobStore, attachment: &Attachment, data: Bytes) -> Result<()> {
+    let format = if attachment.thumb_mime() == "image/jpeg" { ImageFormat::Jpeg } else { ImageFormat::Png };
+    // 解码和缩放比较耗时，放到阻塞线程里做
+    let (thumb, width, height) = web::block(move || make_thumbnail(&data, format)).await??;
+    let thumb_key = format!("{}.thumb", attachment.storage_key);
+    blobs.put(&thumb_key, Bytes::from(thumb)).await?;
+    update_thumbnail(pool, attachment.id, &thumb_key, width, height).await
+}
+
+/// 已发送的附件只有所在房间的成员能下载，未发送的只有上传者自己能下载
+#[get("/attachments/{id}")]
+pub async fn download(state: web::Data<AppState>, path: web::Path<i32>, claims: ClaimsExtractor) -> impl Responder {
+    match readable_attachment(&state, path.into_inner(), claims.sub).await {
+        Ok(attachment) => {
+            let key = attachment.storage_key.clone();
+            let mime = attachment.mime.clone();
+            blob_response(&state, &key, &mime, attachment).await
+        }
+        Err(e) => HttpResponse::Ok().json(ApiResponse::from_err(&e, e.to_string())),
+    }
+}
+
+/// 图片附件的缩略图，权限与原图相同
+#[get("/attachments/{id}/thumbnail")]
+pub async fn thumbnail(state: web::Data<AppState>, path: web::Path<i32>, claims: ClaimsExtractor) -> impl Responder {
+    let attachment = readable_attachment(&state, path.into_inner(), claims.sub).await
+        .and_then(|attachment| match attachment.thumb_key.clone() {
+            Some(key) => Ok((key, attachment)),
+            None => Err(ErrorCode::AttachmentNotFound.error("thumbnail not ready")),
+        });
+    match attachment {
+        Ok((key, attachment)) => blob_response(&state, &key, attachment.thumb_mime(), attachment).await,
+        Err(e) => HttpResponse::Ok().json(ApiResponse::from_err(&e, e.to_string())),
+    }
+}
+
+async fn blob_response(state: &AppState, key: &str, mime: &str, attachment: Attachment) -> HttpResponse {
+    match state.blobs.get(key).await {
+        Ok(data) => {
+            let disposition = if mime.starts_with("image/") { DispositionType::Inline } else { DispositionType::Attachment };
+            HttpResponse::Ok()
+                .content_type(mime)
+                .insert_header(ContentDisposition {
+                    disposition,
+                    parameters: vec![DispositionParam::FilenameExt(ExtendedValue {
+                        charset: Charset::Ext("UTF-8".to_string()),
+                        language_tag: None,
+                        value: attachment.name.into_bytes(),
+                    })],
+                })
+                .insert_header(("X-Content-Type-Options", "nosniff"))
+                .body(data)
+        }
+        Err(e) => {
+            log::error!("读取附件失败: {}", e);
+            HttpResponse::Ok().json(ApiResponse::from_err(&e, format!("读取附件失败：{}", e)))
+        }
+    }
+}
+
+async fn readable_attachment(state: &AppState, id: i32, user_id: u64) -> Result<Attachment> {
+    let attachment = get_attachment(&state.pool, id).await
+        .ok_or_else(|| ErrorCode::AttachmentNotFound.error("attachment not found"))?;
+    let allowed = match attachment.room_id {
+        Some(room_id) => get_room_member(&state.pool, room_id, user_id).await.is_some(),
+        None => attachment.uploader == user_id,
+    };
+    // 所在消息撤回后附件也不能再访问
+    let deleted = match attachment.msg_id {
+        Some(msg_id) => get_chat_msg_by_id(&state.pool, msg_id).await.map(|msg| msg.deleted).unwrap_or(true),
+        None => false,
+    };
+    let allowed = allowed && !deleted;
+    if !allowed {
+        // 不区分不存在和没有权限，避免泄露附件是否存在
+        return Err(ErrorCode::AttachmentNotFound.error("attachment not found"));
+    }
+    Ok(attachment)
+}
@@ -1,2 +1,5 @@
 
-pub mod user_handler;
\ No newline at end of file
+pub mod user_handler;
+pub mod ws_handler;
+pub mod msg_handler;
+pub mod attachment_handler;
\ No newline at end of file
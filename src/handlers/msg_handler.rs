@@ -0,0 +1,19 @@
+use std::sync::Arc;
+
+use actix_web::{post, web, HttpResponse, Responder};
+
+use crate::{chat::{chatserver::ChatState, protocol::ReqSearchMsgs, search::search_msgs}, web::{auth::ClaimsExtractor, common::ApiResponse}};
+
+
+/// 与聊天协议的 SearchMsgs 相同，只搜索当前用户所在的房间
+#[post("/search_msgs")]
+pub async fn search(state: web::Data<Arc<ChatState>>, req: web::Json<ReqSearchMsgs>, claims: ClaimsExtractor) -> impl Responder {
+    let req = req.into_inner();
+    match search_msgs(&state, claims.0.sub, &req).await {
+        Ok(rsp) => HttpResponse::Ok().json(ApiResponse::success(rsp)),
+        Err(e) => {
+            log::error!("搜索消息失败: {}", e);
+            HttpResponse::Ok().json(ApiResponse::from_err(&e, format!("搜索消息失败：{}", e)))
+        }
+    }
+}
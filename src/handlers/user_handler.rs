@@ -4,7 +4,7 @@ use serde::Deserialize;
 use sqlx::MySqlPool;
 use anyhow::Result;
 
-use crate::{dao::user_dao::*, models::user::User, utils::argon2::{password_hash, password_verify}, web::{auth::ClaimsExtractor, common::{ApiResponse, AppState}, jwt::build_token}};
+use crate::{dao::user_dao::*, models::user::User, utils::argon2::{password_hash, password_verify}, web::{auth::ClaimsExtractor, common::{ApiResponse, AppState, ErrorCode}, jwt::build_token}};
 
 
 #[derive(Debug, Deserialize)]
@@ -20,7 +20,7 @@ pub async fn register(state: web::Data<AppState>, user: web::Json<ReqRegister>)
         Ok(_) => HttpResponse::Ok().json(ApiResponse::ok()),
         Err(e) => {
             log::error!("注册失败: {}", e);
-            HttpResponse::Ok().json(ApiResponse::code_err(-1, format!("注册失败:{}", e.to_string())))
+            HttpResponse::Ok().json(ApiResponse::from_err(&e, format!("注册失败:{}", e)))
         }
     }
 }
@@ -28,8 +28,8 @@ pub async fn register(state: web::Data<AppState>, user: web::Json<ReqRegister>)
 async fn _register(pool: &MySqlPool, user: ReqRegister) -> Result<()> {
     let old = get_user_by_name(pool, &user.username).await;
     info!("old: {:?}", old);
-    if let Some(_) = old {
-        return Err(anyhow::anyhow!("用户名已存在"));
+    if old.is_some() {
+        return Err(ErrorCode::UserExists.error("用户名已存在"));
     }
     
     let password_hash = password_hash(&user.password)?;
@@ -53,13 +53,13 @@ pub async fn login(state: web::Data<AppState>, user: web::Json<ReqLogin>) -> imp
                 Ok(token) => HttpResponse::Ok().json(ApiResponse::success(token)),
                 Err(e) => {
                     log::error!("生成token失败: {}", e);
-                    HttpResponse::Ok().json(ApiResponse::code_err(-1, format!("生成token失败：{}", e.to_string())))
+                    HttpResponse::Ok().json(ApiResponse::code_err(-1, format!("生成token失败：{}", e)))
                 }
             }
         }
         Err(e) => {
             log::error!("登录失败: {}", e);
-            HttpResponse::Ok().json(ApiResponse::code_err(-1, format!("登录失败：{}", e.to_string())))
+            HttpResponse::Ok().json(ApiResponse::from_err(&e, format!("登录失败：{}", e)))
         },
     }
 }
@@ -75,20 +75,19 @@ pub async fn update_password(state: web::Data<AppState>, user: web::Json<ReqUpda
     let user = user.into_inner();
     let claims = claims.0;
     info!("update_password: {:?}, {:?}", user, claims);
-    HttpResponse::Ok().json(ApiResponse::msg_ok("修改密码成功"));
     match _update_password(&state.pool, user, claims.sub).await {
         Ok(_) => HttpResponse::Ok().json(ApiResponse::msg_ok("修改密码成功")),
         Err(e) => {
             log::error!("修改密码失败: {}", e);
-            HttpResponse::Ok().json(ApiResponse::code_err(-1, format!("修改密码失败：{}", e.to_string())))
+            HttpResponse::Ok().json(ApiResponse::from_err(&e, format!("修改密码失败：{}", e)))
         },
     }
 }
 
 async fn _update_password(pool: &MySqlPool, req: ReqUpdatePassword, id: u64) -> Result<()> {
-    let user = get_user(pool, id).await.ok_or_else(|| anyhow::anyhow!("用户不存在"))?;
+    let user = get_user(pool, id).await.ok_or_else(|| ErrorCode::UserNotFound.error("用户不存在"))?;
     if !password_verify(&req.old_password, &user.password_hash)? {
-        return Err(anyhow::anyhow!("旧密码错误"));
+        return Err(ErrorCode::WrongPassword.error("旧密码错误"));
     }
     let password_hash = password_hash(&req.new_password)?;
     update_user_password(pool, id, password_hash.as_str()).await?;
@@ -96,9 +95,9 @@ async fn _update_password(pool: &MySqlPool, req: ReqUpdatePassword, id: u64) ->
 }
 
 async fn _login(pool: &MySqlPool, req: ReqLogin) -> Result<User> {
-    let user = get_user_by_name(pool, &req.username).await.ok_or_else(|| anyhow::anyhow!("用户不存在"))?;
+    let user = get_user_by_name(pool, &req.username).await.ok_or_else(|| ErrorCode::UserNotFound.error("用户不存在"))?;
     if !password_verify(&req.password, &user.password_hash)? {
-        return Err(anyhow::anyhow!("密码错误"));
+        return Err(ErrorCode::WrongPassword.error("密码错误"));
     }
     log::info!("登录成功: {}", user.username);
     Ok(user)
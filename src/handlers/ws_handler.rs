@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use actix_web::{get, rt, web, Error, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use log::{error, info};
+use serde::Deserialize;
+
+use tokio::time::Instant;
+
+use crate::{chat::chatserver::{auth_user, close_conn, hand_text, open_conn, ping_conn, ChatState, ConnFormat}, models::user::User};
+
+#[derive(Debug, Deserialize)]
+pub struct WsQuery {
+    pub token: Option<String>,
+}
+
+/// 浏览器使用的 WebSocket 接入，协议与 TCP 聊天服务一致，一个文本帧对应一条消息。
+/// token 可以放在 `?token=` 中，否则第一帧文本作为 token，与 TCP 相同。
+#[get("/ws")]
+pub async fn ws(req: HttpRequest, body: web::Payload, query: web::Query<WsQuery>, state: web::Data<Arc<ChatState>>) -> Result<HttpResponse, Error> {
+    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    let state = state.get_ref().clone();
+    let token = query.into_inner().token;
+    rt::spawn(async move {
+        match hand_ws(state, session, msg_stream, token).await {
+            Ok(_) => info!("ws finish"),
+            Err(e) => error!("ws connect error:{}", e),
+        }
+    });
+    Ok(response)
+}
+
+async fn hand_ws(state: Arc<ChatState>, mut session: Session, mut msg_stream: MessageStream, token: Option<String>) -> anyhow::Result<()> {
+    let token = match token {
+        Some(token) => token,
+        // 和 TCP 一样，迟迟不发 token 的连接按空闲超时处理
+        None => match tokio::time::timeout(state.heartbeat.idle_timeout, wait_token(&mut session, &mut msg_stream)).await {
+            Ok(token) => token?,
+            Err(_) => {
+                let _ = session.close(None).await;
+                return Err(anyhow::anyhow!("ws auth timeout"));
+            }
+        },
+    };
+    let user = match auth_user(&state, &token).await {
+        Ok(user) => user,
+        Err(e) => {
+            let _ = session.close(None).await;
+            return Err(e);
+        }
+    };
+    info!("ws auth user success:{}", user.username);
+    let (conn_id, mut receiver) = open_conn(state.clone(), &user).await?;
+    let mut writer = session.clone();
+    let format = ConnFormat::default();
+    let writer_format = format.clone();
+    rt::spawn(async move {
+        while let Some(msg) = receiver.recv().await {
+            if writer.text(writer_format.outgoing(msg)).await.is_err() {
+                info!("send ws msg error: session closed");
+                break;
+            }
+        }
+    });
+    let r = read_loop(&state, &user, conn_id, &format, &mut session, &mut msg_stream).await;
+    info!("close ws connect:{}", user.username);
+    close_conn(&state, &user, conn_id).await;
+    let _ = session.close(None).await;
+    r
+}
+
+/// 等待第一帧文本作为 token
+async fn wait_token(session: &mut Session, msg_stream: &mut MessageStream) -> anyhow::Result<String> {
+    loop {
+        match msg_stream.recv().await {
+            Some(Ok(Message::Text(text))) => return Ok(text.to_string()),
+            Some(Ok(Message::Ping(bytes))) => {
+                let _ = session.pong(&bytes).await;
+            }
+            Some(Ok(Message::Close(_))) | None => return Err(anyhow::anyhow!("ws closed before auth")),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => return Err(e.into()),
+        }
+    }
+}
+
+async fn read_loop(state: &Arc<ChatState>, user: &User, conn_id: u64, format: &ConnFormat, session: &mut Session, msg_stream: &mut MessageStream) -> anyhow::Result<()> {
+    let heartbeat = state.heartbeat;
+    let mut ticker = heartbeat.ticker();
+    let mut last_active = Instant::now();
+    loop {
+        tokio::select! {
+            msg = msg_stream.recv() => {
+                let Some(msg) = msg else {
+                    break;
+                };
+                last_active = Instant::now();
+                match msg? {
+                    Message::Text(text) => hand_text(state.clone(), user, conn_id, format, &text).await,
+                    Message::Ping(bytes) => {
+                        let _ = session.pong(&bytes).await;
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+            _ = ticker.tick() => {
+                if heartbeat.is_idle(last_active) {
+                    info!("ws idle timeout:{}", user.username);
+                    break;
+                }
+                if let Err(e) = ping_conn(state, user.id, conn_id).await {
+                    error!("ws ping error:{}, {}", user.username, e);
+                    return Err(e);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -4,4 +4,5 @@ pub mod handlers;
 pub mod routers;
 pub mod models;
 pub mod dao;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod storage;
\ No newline at end of file
@@ -1,12 +1,12 @@
-use std::env;
+use std::{env, sync::Arc, time::Duration};
 
 use actix_web::{get, web::Data, App, HttpServer};
-use chat_practise::{chat::chatserver::start_chat_server, routers::config_router, web::{auth::AuthMiddleware, common::AppState}};
+use chat_practise::{chat::chatserver::{start_chat_server, Heartbeat, SendQueue}, routers::config_router, storage::{BlobStore, LocalBlobStore}, web::{auth::AuthMiddleware, common::AppState}};
 use sqlx::mysql::MySqlPoolOptions;
 
 #[get("/")]
 async fn greet() -> String {
-    format!("Hello !")
+    "Hello !".to_string()
 }
 
 #[actix_web::main] // or #[tokio::main]
@@ -15,6 +15,13 @@ async fn main() -> std::io::Result<()> {
     let url = env::var("DATABASE_URL").expect("ENV DATABASE_URL ERROR");
     let chat_port: u16 = env::var("CHAT_PORT").unwrap_or("8081".to_string()).parse().expect("ENV CHAT_PORT ERROR");
     let web_port: u16 = env::var("WEB_PORT").unwrap_or("8080".to_string()).parse().expect("ENV WEB_PORT ERROR");
+    let heartbeat_interval: u64 = env::var("HEARTBEAT_INTERVAL").unwrap_or("5".to_string()).parse().expect("ENV HEARTBEAT_INTERVAL ERROR");
+    let idle_timeout: u64 = env::var("IDLE_TIMEOUT").unwrap_or("15".to_string()).parse().expect("ENV IDLE_TIMEOUT ERROR");
+    let heartbeat = Heartbeat::new(Duration::from_secs(heartbeat_interval), Duration::from_secs(idle_timeout))
+        .map_err(std::io::Error::other)?;
+    let send_queue_size: usize = env::var("SEND_QUEUE_SIZE").unwrap_or("1024".to_string()).parse().expect("ENV SEND_QUEUE_SIZE ERROR");
+    let send_stall_timeout: u64 = env::var("SEND_STALL_TIMEOUT").unwrap_or("10".to_string()).parse().expect("ENV SEND_STALL_TIMEOUT ERROR");
+    let blob_dir = env::var("BLOB_DIR").unwrap_or("data/blobs".to_string());
     env_logger::init_from_env(env_logger::Env::default().default_filter_or("trace"));
 
     let pool = MySqlPoolOptions::new()
@@ -22,14 +29,19 @@ async fn main() -> std::io::Result<()> {
     .connect(url.as_str())
     .await
     .unwrap();
-    let chat_state = start_chat_server(chat_port, pool.clone()).await.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let chat_state = start_chat_server(chat_port, pool.clone(), heartbeat, SendQueue {
+        capacity: send_queue_size.max(1),
+        stall_timeout: Duration::from_secs(send_stall_timeout),
+    }).await.map_err(std::io::Error::other)?;
+
+    let blobs: Arc<dyn BlobStore> = Arc::new(LocalBlobStore::new(blob_dir));
 
     HttpServer::new(move || {
         App::new()
-        .app_data(Data::new(AppState { pool: pool.clone() }))
+        .app_data(Data::new(AppState { pool: pool.clone(), blobs: blobs.clone() }))
         .app_data(Data::new(chat_state.clone()))
         .wrap(AuthMiddleware {
-            whitelist:vec!["/login".to_owned(), "/register".to_owned()]
+            whitelist:vec!["/login".to_owned(), "/register".to_owned(), "/ws".to_owned()]
         })
         .configure(config_router)
     })
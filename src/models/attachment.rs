@@ -0,0 +1,32 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct Attachment {
+    pub id: i32,
+    pub uploader: u64,
+    pub name: String,
+    pub size: i64,
+    pub mime: String,
+    pub sha256: String,
+    /// 在 BlobStore 中的 key
+    pub storage_key: String,
+    /// 发送前为空
+    pub msg_id: Option<i32>,
+    pub room_id: Option<i32>,
+    pub created_at: NaiveDateTime,
+    /// 图片的尺寸
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// 缩略图在 BlobStore 中的 key，后台生成完成前为空
+    pub thumb_key: Option<String>,
+    pub thumb_width: Option<i32>,
+    pub thumb_height: Option<i32>,
+}
+
+impl Attachment {
+    /// JPEG 的缩略图仍是 JPEG，其他格式生成 PNG 以保留透明度
+    pub fn thumb_mime(&self) -> &'static str {
+        if self.mime == "image/jpeg" { "image/jpeg" } else { "image/png" }
+    }
+}
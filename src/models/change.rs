@@ -0,0 +1,35 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct RoomChange {
+    pub id: i64,
+    pub room_id: i32,
+    pub kind: i32,
+    pub msg_id: Option<i32>,
+    pub user_id: Option<u64>,
+    pub created_at: NaiveDateTime,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    NewMsg = 1,
+    MsgEdited = 2,
+    MsgDeleted = 3,
+    MemberJoined = 4,
+    MemberLeft = 5,
+}
+
+impl ChangeKind {
+    pub fn from_i32(kind: i32) -> Option<Self> {
+        match kind {
+            1 => Some(ChangeKind::NewMsg),
+            2 => Some(ChangeKind::MsgEdited),
+            3 => Some(ChangeKind::MsgDeleted),
+            4 => Some(ChangeKind::MemberJoined),
+            5 => Some(ChangeKind::MemberLeft),
+            _ => None,
+        }
+    }
+}
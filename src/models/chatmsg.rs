@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+/// 系统消息（加入、离开房间等）的发送者 id
+pub const SYSTEM_SENDER: u64 = 0;
+
  #[derive(sqlx::FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
     pub id: i32,
@@ -7,4 +10,39 @@ pub struct ChatMessage {
     pub message: String,
     pub sender: u64,
     pub send_time: String,
+    pub edited_at: Option<String>,
+    pub deleted: bool,
+    /// 引用的消息
+    pub reply_to: Option<i32>,
+    /// 所属话题的根消息
+    pub thread_id: Option<i32>,
+    /// 见 [`MsgType`]
+    pub msg_type: i32,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MsgType {
+    Text = 1,
+    /// 服务端过滤过的 markdown
+    Markdown = 2,
+    /// 加入、离开房间等系统通知，只能由服务端发出
+    System = 3,
+    /// 机器人等发送的卡片，message 是卡片的 JSON
+    Card = 4,
+    /// 投票，message 是投票的问题，选项和票数在 polls 相关的表里
+    Poll = 5,
+}
+
+impl MsgType {
+    pub fn from_i32(msg_type: i32) -> Option<Self> {
+        match msg_type {
+            1 => Some(MsgType::Text),
+            2 => Some(MsgType::Markdown),
+            3 => Some(MsgType::System),
+            4 => Some(MsgType::Card),
+            5 => Some(MsgType::Poll),
+            _ => None,
+        }
+    }
 }
\ No newline at end of file
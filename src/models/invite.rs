@@ -0,0 +1,28 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct RoomInvite {
+    pub id: i32,
+    pub room_id: i32,
+    pub inviter: u64,
+    pub invitee: Option<u64>,
+    pub code: Option<String>,
+    pub status: i32,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InviteStatus {
+    Pending = 0,
+    Accepted = 1,
+    Declined = 2,
+}
+
+impl RoomInvite {
+    pub fn is_expired(&self, now: NaiveDateTime) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,25 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct Mention {
+    pub id: i32,
+    pub msg_id: i32,
+    pub room_id: i32,
+    pub user_id: u64,
+    pub sender: u64,
+    pub kind: i32,
+    pub is_read: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MentionKind {
+    /// @username
+    User = 1,
+    /// @all，房间所有成员
+    All = 2,
+    /// @here，发送时在线的成员
+    Here = 3,
+}
@@ -1,4 +1,11 @@
 
 pub mod user;
 pub mod room;
-pub mod chatmsg;
\ No newline at end of file
+pub mod chatmsg;
+pub mod room_member;
+pub mod invite;
+pub mod mention;
+pub mod presence;
+pub mod change;
+pub mod attachment;
+pub mod poll;
\ No newline at end of file
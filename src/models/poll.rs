@@ -0,0 +1,31 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct Poll {
+    pub id: i32,
+    /// 投票所在的消息
+    pub msg_id: i32,
+    pub room_id: i32,
+    pub creator: u64,
+    pub question: String,
+    pub multi_choice: bool,
+    pub closes_at: Option<NaiveDateTime>,
+    pub closed: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl Poll {
+    /// 手动关闭或者已经过了截止时间
+    pub fn is_closed(&self, now: NaiveDateTime) -> bool {
+        self.closed || self.closes_at.is_some_and(|closes_at| closes_at <= now)
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PollOption {
+    pub id: i32,
+    pub poll_id: i32,
+    pub position: i32,
+    pub text: String,
+}
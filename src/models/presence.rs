@@ -0,0 +1,32 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+/// users 表里和在线状态有关的字段
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct UserPresence {
+    pub id: u64,
+    pub status: i32,
+    pub status_text: Option<String>,
+    pub last_seen_at: Option<NaiveDateTime>,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceStatus {
+    Online = 1,
+    /// 客户端设置的离开状态
+    Away = 2,
+    /// 没有任何连接
+    Offline = 3,
+}
+
+impl PresenceStatus {
+    pub fn from_i32(status: i32) -> Option<Self> {
+        match status {
+            1 => Some(PresenceStatus::Online),
+            2 => Some(PresenceStatus::Away),
+            3 => Some(PresenceStatus::Offline),
+            _ => None,
+        }
+    }
+}
@@ -6,5 +6,4 @@ pub struct Room {
     pub id: i32,
     pub room_type: i32,
     pub room_name: String,
-    pub members: String,
 }
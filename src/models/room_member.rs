@@ -0,0 +1,41 @@
+use sqlx::types::chrono::NaiveDateTime;
+
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct RoomMember {
+    pub room_id: i32,
+    pub user_id: u64,
+    pub role: i32,
+    pub joined_at: NaiveDateTime,
+    /// 已读到的最后一条消息 id
+    pub last_read_id: i32,
+}
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomRole {
+    Owner = 1,
+    Admin = 2,
+    Member = 3,
+    /// 只能看，不能发言
+    ReadOnly = 4,
+}
+
+impl RoomRole {
+    pub fn from_i32(role: i32) -> Option<Self> {
+        match role {
+            1 => Some(RoomRole::Owner),
+            2 => Some(RoomRole::Admin),
+            3 => Some(RoomRole::Member),
+            4 => Some(RoomRole::ReadOnly),
+            _ => None,
+        }
+    }
+}
+
+impl RoomMember {
+    /// 未知的角色值按只读处理
+    pub fn role(&self) -> RoomRole {
+        RoomRole::from_i32(self.role).unwrap_or(RoomRole::ReadOnly)
+    }
+}
\ No newline at end of file
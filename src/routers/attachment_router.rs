@@ -0,0 +1,9 @@
+use actix_web::web;
+
+use crate::handlers::attachment_handler;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(attachment_handler::upload)
+    .service(attachment_handler::download)
+    .service(attachment_handler::thumbnail);
+}
@@ -1,8 +1,17 @@
 use actix_web::web;
 
 pub mod user_router;
+pub mod ws_router;
+pub mod msg_router;
+pub mod attachment_router;
 
 pub fn config_router(cfg: &mut web::ServiceConfig) {
     // 注册用户路由
     user_router::config(cfg);
+    // 浏览器聊天接入
+    ws_router::config(cfg);
+    // 消息搜索等
+    msg_router::config(cfg);
+    // 附件上传下载
+    attachment_router::config(cfg);
 }
\ No newline at end of file
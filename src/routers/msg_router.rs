@@ -0,0 +1,7 @@
+use actix_web::web;
+
+use crate::handlers::msg_handler;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(msg_handler::search);
+}
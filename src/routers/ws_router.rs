@@ -0,0 +1,7 @@
+use actix_web::web;
+
+use crate::handlers::ws_handler;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(ws_handler::ws);
+}
\ No newline at end of file
@@ -0,0 +1,62 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt};
+
+use crate::web::common::ErrorCode;
+
+use super::BlobStore;
+
+/// 把内容存成 root 下的文件，key 中的 `/` 对应子目录
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// 只允许普通的相对路径，防止 key 跳出 root
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(ErrorCode::BadRequest.error(format!("invalid blob key:{}", key)));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put<'a>(&'a self, key: &'a str, data: Bytes) -> BoxFuture<'a, Result<()>> {
+        async move {
+            let path = self.path(key)?;
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            // 先写临时文件再改名，避免读到写了一半的内容
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+            tokio::fs::write(&tmp, &data).await?;
+            tokio::fs::rename(&tmp, &path).await?;
+            Ok(())
+        }.boxed()
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Bytes>> {
+        async move {
+            let path = self.path(key)?;
+            Ok(Bytes::from(tokio::fs::read(path).await?))
+        }.boxed()
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>> {
+        async move {
+            match tokio::fs::remove_file(self.path(key)?).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        }.boxed()
+    }
+}
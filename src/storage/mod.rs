@@ -0,0 +1,22 @@
+//! 附件等二进制内容的存储
+//!
+//! 业务代码只依赖 [`BlobStore`]，目前只有本地文件系统的实现 [`LocalBlobStore`]，
+//! 以后换成对象存储时新增一个实现即可。
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+
+mod local;
+
+pub use local::LocalBlobStore;
+
+pub trait BlobStore: Send + Sync {
+    /// 写入内容，key 已存在时覆盖
+    fn put<'a>(&'a self, key: &'a str, data: Bytes) -> BoxFuture<'a, Result<()>>;
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Bytes>>;
+
+    /// key 不存在时不报错
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<()>>;
+}
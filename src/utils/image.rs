@@ -0,0 +1,83 @@
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+
+/// 根据文件头识别图片格式，返回对应的 MIME 类型
+pub fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// 缩略图的最大边长
+pub const THUMBNAIL_SIZE: u32 = 320;
+
+fn reader(data: &[u8]) -> Result<ImageReader<Cursor<&[u8]>>> {
+    let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    // 防止超大尺寸的图片耗尽内存
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(16384);
+    limits.max_image_height = Some(16384);
+    limits.max_alloc = Some(256 * 1024 * 1024);
+    reader.limits(limits);
+    Ok(reader)
+}
+
+/// 只读文件头取得图片尺寸
+pub fn image_size(data: &[u8]) -> Result<(u32, u32)> {
+    Ok(reader(data)?.into_dimensions()?)
+}
+
+/// 生成不超过 THUMBNAIL_SIZE 的缩略图，保持宽高比，小图不放大；返回编码后的内容和尺寸
+pub fn make_thumbnail(data: &[u8], format: ImageFormat) -> Result<(Vec<u8>, u32, u32)> {
+    let image = reader(data)?.decode()?;
+    let thumb = if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+        image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+    } else {
+        image
+    };
+    // JPEG 不支持透明通道
+    let thumb = if format == ImageFormat::Jpeg { DynamicImage::ImageRgb8(thumb.to_rgb8()) } else { thumb };
+    let mut buf = Cursor::new(Vec::new());
+    thumb.write_to(&mut buf, format)?;
+    Ok((buf.into_inner(), thumb.width(), thumb.height()))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_sniff_image() {
+        assert_eq!(sniff_image(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff_image(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(sniff_image(b"GIF89a"), Some("image/gif"));
+        assert_eq!(sniff_image(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_image(b"%PDF-1.7"), None);
+    }
+
+    #[test]
+    fn test_make_thumbnail() -> Result<()> {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgba8(1280, 640).write_to(&mut png, ImageFormat::Png)?;
+        let png = png.into_inner();
+        assert_eq!(image_size(&png)?, (1280, 640));
+        let (thumb, width, height) = make_thumbnail(&png, ImageFormat::Png)?;
+        assert_eq!((width, height), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        assert_eq!(sniff_image(&thumb), Some("image/png"));
+        let (thumb, width, height) = make_thumbnail(&png, ImageFormat::Jpeg)?;
+        assert_eq!((width, height), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        assert_eq!(sniff_image(&thumb), Some("image/jpeg"));
+        Ok(())
+    }
+}
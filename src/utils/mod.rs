@@ -1,2 +1,4 @@
 
-pub mod argon2;
\ No newline at end of file
+pub mod argon2;
+pub mod random;
+pub mod image;
\ No newline at end of file
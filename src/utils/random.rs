@@ -0,0 +1,9 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+
+
+/// 生成 `bytes` 个随机字节的十六进制字符串，用于邀请码等
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    OsRng.fill_bytes(&mut buf);
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
\ No newline at end of file
@@ -32,7 +32,7 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareService {
-             service: service,
+             service,
              whitelist: self.whitelist.clone(),
             }))
     }
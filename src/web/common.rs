@@ -1,8 +1,14 @@
+use std::{fmt, sync::Arc};
+
 use serde::Serialize;
 
+use crate::storage::BlobStore;
+
 
 pub struct AppState {
     pub pool: sqlx::mysql::MySqlPool,
+    /// 附件等上传文件的存储
+    pub blobs: Arc<dyn BlobStore>,
 }
 
 #[derive(Debug, Serialize)]
@@ -12,6 +18,80 @@ pub struct ApiResponse<T: Serialize> {
     pub data: Option<T>,
 }
 
+/// 错误码表，HTTP 接口的 `ApiResponse.code` 和聊天协议的 `Error` 事件共用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum ErrorCode {
+    Unknown = -1,
+    BadRequest = 1000,
+    Unauthorized = 1001,
+    Forbidden = 1002,
+    NotFound = 1003,
+    RoomNotFound = 1004,
+    UserNotFound = 1005,
+    UserExists = 1006,
+    WrongPassword = 1007,
+    UnknownCommand = 1008,
+    UnsupportedVersion = 1009,
+    NotRoomMember = 1010,
+    InviteNotFound = 1011,
+    InviteExpired = 1012,
+    MsgNotFound = 1013,
+    AttachmentNotFound = 1014,
+    FileTooLarge = 1015,
+    FileTypeNotAllowed = 1016,
+    PollNotFound = 1017,
+    PollClosed = 1018,
+    Database = 1500,
+}
+
+impl ErrorCode {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// 生成带错误码的 anyhow 错误
+    pub fn error<E: AsRef<str>>(self, message: E) -> anyhow::Error {
+        CodeError { code: self, message: message.as_ref().to_string() }.into()
+    }
+
+    /// 回给客户端的错误信息；数据库错误和未知错误可能带有 SQL 片段等内部细节，只返回固定的说明，完整错误只记在服务端日志里
+    pub fn client_message(e: &anyhow::Error) -> String {
+        match Self::of(e) {
+            ErrorCode::Database => "database error".to_string(),
+            ErrorCode::Unknown => "internal error".to_string(),
+            _ => e.to_string(),
+        }
+    }
+
+    /// 从错误链中识别错误码，识别不了的归为 Unknown
+    pub fn of(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<CodeError>() {
+            e.code
+        } else if e.downcast_ref::<sqlx::Error>().is_some() {
+            ErrorCode::Database
+        } else if e.downcast_ref::<serde_json::Error>().is_some() {
+            ErrorCode::BadRequest
+        } else {
+            ErrorCode::Unknown
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CodeError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl fmt::Display for CodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CodeError {}
+
 impl ApiResponse<()> {
     pub fn ok() -> Self {
         Self::msg_ok("Success")
@@ -36,6 +116,10 @@ impl ApiResponse<()> {
             data: None,
         }
     }
+
+    pub fn from_err<E: AsRef<str>>(e: &anyhow::Error, message: E) -> Self {
+        Self::code_err(ErrorCode::of(e).code(), message)
+    }
 }
 
 impl<T: Serialize> ApiResponse<T> {
@@ -19,7 +19,7 @@ pub fn build_token(id: u64) -> Result<String> {
         sub: id,
         exp: exp.timestamp() as usize,
     };
-    Ok(encode(&Header::default(), &my_claims, &EncodingKey::from_secret(SECRET_KEY.as_ref()))?)
+    Ok(encode(&Header::default(), &my_claims, &EncodingKey::from_secret(SECRET_KEY))?)
 }
 
 pub fn validate_jwt(token: &str) -> Result<Claims> {